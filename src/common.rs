@@ -184,6 +184,157 @@ fn reverse_at_index(v: &mut Vec<Mark>, idx: Idx, length: usize) -> Result<(), St
     }
 }
 
+/// SplitMix64; deterministic for a given seed, which is what we want for
+/// reproducible test fixtures. Not suitable for real secrets.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in 0..bound (bound must be > 0).
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        let bound = bound as u64;
+        // Reject the top end of the range that would bias the modulo
+        let zone = u64::max_value() - (u64::max_value() % bound);
+        loop {
+            let r = self.next_u64();
+            if r < zone {
+                return (r % bound) as usize;
+            }
+        }
+    }
+}
+
+/// Shape of the sequence you get by repeatedly applying a step function to a
+/// state: `transient_len` steps before the first state that is ever repeated,
+/// then a loop of `cycle_len` states.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Cycle {
+    pub transient_len: usize,
+    pub cycle_len: usize,
+}
+
+impl Cycle {
+    /// Steps until the first repeated state is seen again.
+    pub fn repeats_after(&self) -> usize {
+        self.transient_len + self.cycle_len
+    }
+
+    /// The smallest number of steps that ends in the same state as taking
+    /// `steps` steps.
+    pub fn equivalent_steps(&self, steps: usize) -> usize {
+        if steps <= self.transient_len {
+            steps
+        } else {
+            self.transient_len + (steps - self.transient_len) % self.cycle_len
+        }
+    }
+}
+
+/// Finds the cycle reached from `initial` using Brent's algorithm, which only
+/// ever holds on to a couple of states at a time.
+///
+/// The sequence must actually loop at some point (e.g. because there are only
+/// finitely many states), otherwise this never returns.
+pub fn find_cycle<S, F>(initial: S, step: F) -> Cycle
+where
+    S: Clone + PartialEq,
+    F: FnMut(&S) -> S,
+{
+    match find_cycle_within(initial, step, usize::max_value()) {
+        Ok(cycle) => cycle,
+        Err(_) => unreachable!("Took usize::max_value() steps without finding a cycle"),
+    }
+}
+
+/// `find_cycle`, giving up after `max_steps` steps and handing back the state
+/// reached by then, for when that's all that's needed.
+///
+/// A cycle is always found within `3 * repeats_after()` steps, but may be
+/// found too late even if it repeats within `max_steps`.
+pub fn find_cycle_within<S, F>(initial: S, mut step: F, max_steps: usize) -> Result<Cycle, S>
+where
+    S: Clone + PartialEq,
+    F: FnMut(&S) -> S,
+{
+    let mut detector = CycleDetector::new(initial.clone());
+    let mut current = initial.clone();
+    for _ in 0..max_steps {
+        current = step(&current);
+        if let Some(cycle_len) = detector.push(&current) {
+            return Ok(Cycle {
+                transient_len: transient_len(initial, step, cycle_len),
+                cycle_len,
+            });
+        }
+    }
+    Err(current)
+}
+
+/// The first half of Brent's algorithm, for sequences that are stepped through
+/// elsewhere: each state is checked against states left at increasing powers
+/// of 2 steps, so the cycle length is known once one comes round again.
+pub struct CycleDetector<S> {
+    checkpoint: S,
+    power: usize,
+    since_checkpoint: usize,
+}
+
+impl<S> CycleDetector<S>
+where
+    S: Clone + PartialEq,
+{
+    pub fn new(initial: S) -> CycleDetector<S> {
+        CycleDetector {
+            checkpoint: initial,
+            power: 1,
+            since_checkpoint: 0,
+        }
+    }
+
+    /// Takes the state after one more step, giving the cycle length if it has
+    /// been seen before.
+    pub fn push(&mut self, state: &S) -> Option<usize> {
+        self.since_checkpoint += 1;
+        if *state == self.checkpoint {
+            return Some(self.since_checkpoint);
+        }
+        if self.since_checkpoint == self.power {
+            self.checkpoint = state.clone();
+            self.power *= 2;
+            self.since_checkpoint = 0;
+        }
+        None
+    }
+}
+
+/// The second half of Brent's algorithm: with one run a cycle ahead of the
+/// other, they meet at the start of the cycle.
+pub fn transient_len<S, F>(initial: S, mut step: F, cycle_len: usize) -> usize
+where
+    S: Clone + PartialEq,
+    F: FnMut(&S) -> S,
+{
+    let mut tortoise = initial.clone();
+    let mut hare = initial;
+    for _ in 0..cycle_len {
+        hare = step(&hare);
+    }
+    let mut transient_len = 0;
+    while tortoise != hare {
+        tortoise = step(&tortoise);
+        hare = step(&hare);
+        transient_len += 1;
+    }
+    transient_len
+}
+
 #[cfg(test)]
 mod tests {
     use common::*;
@@ -220,4 +371,77 @@ mod tests {
         let input = vec![65, 27, 9, 1, 4, 3, 40, 50, 91, 7, 6, 0, 2, 5, 68, 22];
         assert_eq!(to_dense_bitxored(&input, 16)[0], 64);
     }
+
+    #[test]
+    fn find_cycle_test() {
+        // 0 -> 1 -> 2 -> 3 -> 4 -> 2
+        let cycle = find_cycle(0, |x| if *x == 4 { 2 } else { x + 1 });
+        assert_eq!(
+            cycle,
+            Cycle {
+                transient_len: 2,
+                cycle_len: 3,
+            }
+        );
+        assert_eq!(cycle.repeats_after(), 5);
+        // Loops straight away
+        let cycle = find_cycle(7, |x| *x);
+        assert_eq!(
+            cycle,
+            Cycle {
+                transient_len: 0,
+                cycle_len: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn find_cycle_matches_naive_test() {
+        use std::collections::HashMap;
+        for modulus in 1..200 {
+            let step = |x: &u64| (x * x + 1) % modulus;
+            let mut seen_at = HashMap::new();
+            let mut current = 3 % modulus;
+            let mut i = 0;
+            while !seen_at.contains_key(&current) {
+                seen_at.insert(current, i);
+                current = step(&current);
+                i += 1;
+            }
+            let transient_len = seen_at[&current];
+            let expected = Cycle {
+                transient_len,
+                cycle_len: i - transient_len,
+            };
+            assert_eq!(find_cycle(3 % modulus, step), expected);
+        }
+    }
+
+    #[test]
+    fn find_cycle_within_test() {
+        // 0 -> 1 -> 2 -> 3 -> 4 -> 2, only noticed on getting back to 3 after 6 steps
+        let step = |x: &u64| if *x == 4 { 2 } else { x + 1 };
+        assert_eq!(find_cycle_within(0, step, 3), Err(3));
+        assert_eq!(find_cycle_within(0, step, 5), Err(2));
+        assert_eq!(
+            find_cycle_within(0, step, 6),
+            Ok(Cycle {
+                transient_len: 2,
+                cycle_len: 3,
+            })
+        );
+        assert_eq!(find_cycle_within(0, step, 0), Err(0));
+    }
+
+    #[test]
+    fn equivalent_steps_test() {
+        let cycle = Cycle {
+            transient_len: 2,
+            cycle_len: 3,
+        };
+        assert_eq!(cycle.equivalent_steps(0), 0);
+        assert_eq!(cycle.equivalent_steps(2), 2);
+        assert_eq!(cycle.equivalent_steps(5), 2);
+        assert_eq!(cycle.equivalent_steps(1_000_000_000), 4);
+    }
 }
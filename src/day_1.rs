@@ -1,37 +1,165 @@
+use std::io::{ErrorKind, Read};
+use std::io;
+
 const RADIX: u32 = 10;
+const STREAM_CHUNK_SIZE: usize = 8192;
 
 const DAY_1_INPUT: &str = include_str!("../data/day_1_input");
 
 pub fn run() -> Result<(), &'static str> {
     println!("*** Day 1: Inverse Captcha ***");
     println!("Input: {}", DAY_1_INPUT);
-    println!("Solution: {}\n", sum_match_nexts(DAY_1_INPUT));
+    println!("Solution 1: {}\n", sum_match_nexts(DAY_1_INPUT));
+    println!("Solution 2: {}\n", sum_match_halfway(DAY_1_INPUT));
     Ok(())
 }
 
-fn string_to_digits(s: &str) -> Vec<u8> {
+pub fn string_to_digits(s: &str) -> Vec<u8> {
+    string_to_digits_with_radix(s, RADIX)
+}
+
+/// Like `string_to_digits`, but for captchas written in any radix
+/// supported by `char::to_digit` (2 to 36), e.g. 16 for hex captchas.
+///
+/// Panics if the radix is outside of that range.
+pub fn string_to_digits_with_radix(s: &str, radix: u32) -> Vec<u8> {
+    assert_radix(radix);
     s.chars()
-        .filter_map(|c| c.to_digit(RADIX))
+        .filter_map(|c| c.to_digit(radix))
         .map(|d| d as u8)
         .collect()
 }
 
+fn assert_radix(radix: u32) -> () {
+    assert!(
+        radix >= 2 && radix <= 36,
+        "Radix must be from 2 to 36, got {}",
+        radix
+    );
+}
+
 fn sum_match_nexts(num_s: &str) -> u64 {
-    fn go(nums: &Vec<u8>) -> u64 {
-        let tail_with_first = {
-            let mut t: Vec<_> = nums.into_iter().skip(1).map(|i| *i).collect();
-            if nums.len() > 0 {
-                t.push(nums[0]);
+    let nums = string_to_digits(num_s);
+    sum_matching_at_offset(&nums, 1)
+}
+
+fn sum_match_halfway(num_s: &str) -> u64 {
+    let nums = string_to_digits(num_s);
+    let halfway = nums.len() / 2;
+    sum_matching_at_offset(&nums, halfway)
+}
+
+/// Sums every digit that matches the digit `offset` places further along,
+/// treating the digits as circular.
+///
+/// Offsets larger than the number of digits wrap around as well.
+pub fn sum_matching_at_offset(digits: &[u8], offset: usize) -> u64 {
+    let len = digits.len();
+    if len == 0 {
+        0
+    } else {
+        let offset = offset % len;
+        digits
+            .iter()
+            .enumerate()
+            .filter(|&(idx, d)| *d == digits[(idx + offset) % len])
+            .fold(0, |acc, (_, d)| acc + *d as u64)
+    }
+}
+
+/// Solves a captcha a digit at a time, so that arbitrarily long captchas can be
+/// checked without holding them in memory.
+///
+/// Only the first `offset` digits (needed to handle wrapping around at the end)
+/// and a ring buffer of the last `offset` digits are kept, so checking against
+/// the next digit (offset 1) runs in constant memory.
+pub struct StreamingCaptcha {
+    offset: usize,
+    head: Vec<u8>,
+    ring: Vec<u8>,
+    seen: u64,
+    sum: u64,
+}
+
+impl StreamingCaptcha {
+    pub fn new(offset: usize) -> StreamingCaptcha {
+        StreamingCaptcha {
+            offset,
+            head: Vec::with_capacity(offset),
+            ring: Vec::with_capacity(offset),
+            seen: 0,
+            sum: 0,
+        }
+    }
+
+    pub fn push(&mut self, digit: u8) -> () {
+        if self.offset == 0 {
+            // Every digit matches itself
+            self.sum += digit as u64;
+        } else if self.seen < self.offset as u64 {
+            self.head.push(digit);
+            self.ring.push(digit);
+        } else {
+            let slot = (self.seen % self.offset as u64) as usize;
+            if self.ring[slot] == digit {
+                self.sum += digit as u64;
             }
-            t
-        };
-        nums.into_iter()
-            .zip(tail_with_first.iter())
-            .filter(|&(i, j)| i == j)
-            .fold(0, |acc, (v, _)| *v as u64 + acc)
+            self.ring[slot] = digit;
+        }
+        self.seen += 1;
     }
-    let nums = string_to_digits(num_s);
-    go(&nums)
+
+    /// Finishes off the captcha by comparing the last `offset` digits against
+    /// the first ones.
+    pub fn finish(self) -> u64 {
+        let offset = self.offset as u64;
+        if self.offset == 0 || self.seen == 0 {
+            self.sum
+        } else if self.seen <= offset {
+            // Wrapped around at least once; everything we saw is in head.
+            sum_matching_at_offset(&self.head, self.offset)
+        } else {
+            (self.seen - offset..self.seen).fold(self.sum, |acc, idx| {
+                let digit = self.ring[(idx % offset) as usize];
+                let wrapped_to = self.head[(idx + offset - self.seen) as usize];
+                if digit == wrapped_to {
+                    acc + digit as u64
+                } else {
+                    acc
+                }
+            })
+        }
+    }
+}
+
+/// Streaming version of `sum_matching_at_offset` over the digits read from `reader`.
+///
+/// Bytes that are not digits in the given radix are skipped, just like in
+/// `string_to_digits_with_radix`, and it panics on the same radixes.
+pub fn stream_sum_matching_at_offset<R>(
+    mut reader: R,
+    offset: usize,
+    radix: u32,
+) -> io::Result<u64>
+where
+    R: Read,
+{
+    assert_radix(radix);
+    let mut captcha = StreamingCaptcha::new(offset);
+    let mut buf = [0; STREAM_CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => for b in buf[..read].iter() {
+                if let Some(d) = (*b as char).to_digit(radix) {
+                    captcha.push(d as u8);
+                }
+            },
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(captcha.finish())
 }
 
 #[cfg(test)]
@@ -43,4 +171,76 @@ mod tests {
         assert_eq!(string_to_digits("1234"), vec![1, 2, 3, 4]);
     }
 
+    #[test]
+    #[should_panic(expected = "Radix must be from 2 to 36, got 37")]
+    fn string_to_digits_with_radix_too_high_test() {
+        string_to_digits_with_radix("1", 37);
+    }
+
+    #[test]
+    fn string_to_digits_with_radix_test() {
+        assert_eq!(string_to_digits_with_radix("1aF", 16), vec![1, 10, 15]);
+    }
+
+    #[test]
+    fn sum_match_nexts_test() {
+        assert_eq!(sum_match_nexts("1122"), 3);
+        assert_eq!(sum_match_nexts("1111"), 4);
+        assert_eq!(sum_match_nexts("1234"), 0);
+        assert_eq!(sum_match_nexts("91212129"), 9);
+    }
+
+    #[test]
+    fn sum_match_halfway_test() {
+        assert_eq!(sum_match_halfway("1212"), 6);
+        assert_eq!(sum_match_halfway("1221"), 0);
+        assert_eq!(sum_match_halfway("123425"), 4);
+        assert_eq!(sum_match_halfway("123123"), 12);
+        assert_eq!(sum_match_halfway("12131415"), 4);
+    }
+
+    #[test]
+    fn sum_matching_at_offset_test() {
+        assert_eq!(sum_matching_at_offset(&vec![], 1), 0);
+        assert_eq!(sum_matching_at_offset(&vec![1, 2, 1, 2], 0), 6);
+        assert_eq!(sum_matching_at_offset(&vec![1, 2, 1, 2], 6), 6);
+        assert_eq!(sum_matching_at_offset(&vec![1, 2, 3, 1, 2, 3], 3), 12);
+        let hex = string_to_digits_with_radix("ff1ff", 16);
+        assert_eq!(sum_matching_at_offset(&hex, 1), 45);
+    }
+
+    #[test]
+    fn stream_sum_matching_at_offset_test() {
+        let inputs = vec!["", "1", "11", "1122", "91212129", "12131415", "1a2\n3x1\r\n23"];
+        for input in inputs {
+            let digits = string_to_digits(input);
+            for offset in 0..10 {
+                let streamed = stream_sum_matching_at_offset(input.as_bytes(), offset, RADIX);
+                assert_eq!(
+                    streamed.unwrap(),
+                    sum_matching_at_offset(&digits, offset),
+                    "input: {:?}, offset: {}",
+                    input,
+                    offset
+                );
+            }
+        }
+        let hex = stream_sum_matching_at_offset("ff1ff".as_bytes(), 1, 16);
+        assert_eq!(hex.unwrap(), 45);
+    }
+
+    #[test]
+    fn stream_sum_matching_at_offset_real_test() {
+        let halfway = string_to_digits(DAY_1_INPUT).len() / 2;
+        let streamed_1 = stream_sum_matching_at_offset(DAY_1_INPUT.as_bytes(), 1, RADIX);
+        let streamed_2 = stream_sum_matching_at_offset(DAY_1_INPUT.as_bytes(), halfway, RADIX);
+        assert_eq!(streamed_1.unwrap(), 1177);
+        assert_eq!(streamed_2.unwrap(), 1060);
+    }
+
+    #[test]
+    fn real_test() {
+        assert_eq!(sum_match_nexts(DAY_1_INPUT), 1177);
+        assert_eq!(sum_match_halfway(DAY_1_INPUT), 1060);
+    }
 }
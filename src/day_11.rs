@@ -90,10 +90,10 @@ impl FromStr for Step {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-struct HexAxialCoord {
-    q: isize,
-    r: isize,
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct HexAxialCoord {
+    pub q: isize,
+    pub r: isize,
 }
 
 impl HexAxialCoord {
@@ -113,7 +113,7 @@ struct HexCubeCoord {
     z: isize,
 }
 
-trait StepsDistance {
+pub trait StepsDistance {
     fn steps_from(&self, other: &Self) -> usize;
 }
 
@@ -6,6 +6,8 @@ use combine::easy::*;
 use std::collections::HashMap;
 use std::error::Error;
 
+use common::find_cycle_within;
+
 const DAY_16_INPUT: &'static str = include_str!("../data/day_16_input");
 
 pub fn run() -> Result<(), Box<Error>> {
@@ -89,71 +91,80 @@ struct Dancer<'a> {
     ops: &'a Vec<Op>,
     line: &'a mut Vec<char>,
     lookup: HashMap<char, usize>,
-    cache: HashMap<Vec<char>, (Vec<char>, HashMap<char, usize>)>,
 }
 
 impl<'a> Dancer<'a> {
     fn new(ops: &'a Vec<Op>, line: &'a mut Vec<char>) -> Dancer<'a> {
-        let lookup = line.iter().enumerate().map(|(idx, c)| (*c, idx)).collect();
-        let cache = HashMap::new();
-        Dancer {
-            ops,
-            line,
-            lookup,
-            cache,
-        }
+        let lookup = line_lookup(line);
+        Dancer { ops, line, lookup }
     }
 
+    /// Dances `repeat` times, skipping every full trip around the loop that the
+    /// line eventually falls into.
+    ///
+    /// The loop is only looked for as far as `repeat` dances, so short dances
+    /// don't pay for finding it.
     fn dance(&mut self, repeat: usize) -> () {
+        let ops = self.ops;
+        let step = |line: &Vec<char>| {
+            let mut next = line.clone();
+            Dancer::new(ops, &mut next).dance_once();
+            next
+        };
+        match find_cycle_within(self.line.clone(), step, repeat) {
+            Ok(cycle) => {
+                for _ in 0..cycle.equivalent_steps(repeat) {
+                    self.dance_once();
+                }
+            }
+            Err(line) => {
+                self.lookup = line_lookup(&line);
+                *self.line = line;
+            }
+        }
+    }
+
+    fn dance_once(&mut self) -> () {
         use day_16::Op::*;
         let line_len = self.line.len();
-        let mut cycles_run = 0;
-        while cycles_run < repeat {
-            if let Some((cached_result, cached_lookup)) =
-                self.cache.get(self.line).map(|r| r.clone())
-            {
-                *self.line = cached_result;
-                self.lookup = cached_lookup;
-            } else {
-                let init = self.line.clone();
-                for op in self.ops.iter() {
-                    match op {
-                        &Spin(tail_len) => {
-                            let v = {
-                                let (head, tail) = self.line.split_at(line_len - tail_len);
-                                let mut new_vec = Vec::with_capacity(line_len);
-                                for (idx, c) in tail.iter().chain(head.iter()).enumerate() {
-                                    self.lookup.insert(*c, idx);
-                                    new_vec.push(*c);
-                                }
-                                new_vec
-                            };
-                            *self.line = v;
-                        }
-                        &Exchange(idx_1, idx_2) => {
-                            self.line.swap(idx_1, idx_2);
-                            self.lookup.insert(self.line[idx_1], idx_1);
-                            self.lookup.insert(self.line[idx_2], idx_2);
+        for op in self.ops.iter() {
+            match op {
+                &Spin(tail_len) => {
+                    let v = {
+                        let (head, tail) = self.line.split_at(line_len - tail_len);
+                        let mut new_vec = Vec::with_capacity(line_len);
+                        for (idx, c) in tail.iter().chain(head.iter()).enumerate() {
+                            self.lookup.insert(*c, idx);
+                            new_vec.push(*c);
                         }
-                        &Partner(char_1, char_2) => {
-                            if let Some(char_1_idx) = self.lookup.get(&char_1).map(|u| *u) {
-                                if let Some(char_2_idx) = self.lookup.get(&char_2).map(|u| *u) {
-                                    self.line.swap(char_1_idx, char_2_idx);
-                                    self.lookup.insert(self.line[char_1_idx], char_1_idx);
-                                    self.lookup.insert(self.line[char_2_idx], char_2_idx);
-                                }
-                            };
+                        new_vec
+                    };
+                    *self.line = v;
+                }
+                &Exchange(idx_1, idx_2) => {
+                    self.line.swap(idx_1, idx_2);
+                    self.lookup.insert(self.line[idx_1], idx_1);
+                    self.lookup.insert(self.line[idx_2], idx_2);
+                }
+                &Partner(char_1, char_2) => {
+                    if let Some(char_1_idx) = self.lookup.get(&char_1).map(|u| *u) {
+                        if let Some(char_2_idx) = self.lookup.get(&char_2).map(|u| *u) {
+                            self.line.swap(char_1_idx, char_2_idx);
+                            self.lookup.insert(self.line[char_1_idx], char_1_idx);
+                            self.lookup.insert(self.line[char_2_idx], char_2_idx);
                         }
-                    }
+                    };
                 }
-                self.cache
-                    .insert(init, (self.line.clone(), self.lookup.clone()));
             }
-            cycles_run += 1;
         }
     }
 }
 
+/// Where each program is in the line.
+fn line_lookup(line: &Vec<char>) -> HashMap<char, usize> {
+    line.iter().enumerate().map(|(idx, c)| (*c, idx)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use day_16::*;
@@ -172,6 +183,23 @@ mod tests {
         assert_eq!(dancer.line, &vec!['b', 'a', 'e', 'd', 'c'])
     }
 
+    #[test]
+    fn dance_skips_loops_test() {
+        let ops = vec![Spin(1), Exchange(3, 4), Partner('e', 'b')];
+        for repeat in 0..50 {
+            let mut skipping = test_input();
+            Dancer::new(&ops, &mut skipping).dance(repeat);
+            let mut one_by_one = test_input();
+            {
+                let mut dancer = Dancer::new(&ops, &mut one_by_one);
+                for _ in 0..repeat {
+                    dancer.dance_once();
+                }
+            }
+            assert_eq!(skipping, one_by_one);
+        }
+    }
+
     #[test]
     fn test_parse_real_input() {
         let (ops, _) = Op::parse_many(DAY_16_INPUT).unwrap();
@@ -191,4 +219,15 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn find_solution_2_real_test() {
+        let mut line = input_line();
+        let (ops, _) = Op::parse_many(DAY_16_INPUT).unwrap();
+        let mut dancer = Dancer::new(&ops, &mut line);
+        dancer.dance(1);
+        dancer.dance(1000000000 - 1);
+        let solution_2: String = dancer.line.iter().collect();
+        assert_eq!(solution_2, "lgmkacfjbopednhi");
+    }
 }
@@ -1,21 +1,40 @@
+use std::fmt;
+
 const DAY_2_INPUT: &str = include_str!("../data/day_2_input");
 
-pub fn run() -> Result<(), &'static str> {
+pub fn run() -> Result<(), String> {
     println!("*** Day 2: Corruption Checksum ***");
 
     println!("Input: {}", DAY_2_INPUT);
-    println!("Solution: {}\n", checksum(DAY_2_INPUT));
+    println!(
+        "Solution 1: {}\n",
+        checksum(DAY_2_INPUT).map_err(|e| e.to_string())?
+    );
+    println!(
+        "Solution 2: {}\n",
+        evenly_divisible_checksum(DAY_2_INPUT).map_err(|e| e.to_string())?
+    );
     Ok(())
 }
 
-fn checksum(s: &str) -> isize {
-    let m = string_to_matrix(s);
-    matrix_checksum(&m)
+/// Reduces a single row of the spreadsheet to the number it contributes to
+/// the checksum, or None if the row has no answer.
+///
+/// Closures of the form `Fn(&[isize]) -> Option<isize>` are reducers too.
+pub trait RowReducer {
+    fn reduce(&self, row: &[isize]) -> Option<isize>;
 }
 
-fn matrix_checksum(matrix: &Vec<Vec<isize>>) -> isize {
-    let max_mins = matrix.into_iter().map(|v| {
-        v.into_iter().fold(None, |acc, next| match acc {
+/// Difference between the largest and smallest values in a row (part 1).
+pub struct Range;
+
+/// Result of dividing the only two values in a row where one evenly divides
+/// the other (part 2).
+pub struct EvenlyDivisibleQuotient;
+
+impl RowReducer for Range {
+    fn reduce(&self, row: &[isize]) -> Option<isize> {
+        let max_min = row.into_iter().fold(None, |acc, next| match acc {
             Some((min, max)) => if next < min {
                 Some((next, max))
             } else if next > max {
@@ -24,22 +43,254 @@ fn matrix_checksum(matrix: &Vec<Vec<isize>>) -> isize {
                 Some((min, max))
             },
             None => Some((next, next)),
+        });
+        max_min.map(|(min, max)| max - min)
+    }
+}
+
+impl RowReducer for EvenlyDivisibleQuotient {
+    fn reduce(&self, row: &[isize]) -> Option<isize> {
+        row.iter()
+            .enumerate()
+            .filter_map(|(i, dividend)| {
+                row.iter()
+                    .enumerate()
+                    .find(|&(j, divisor)| i != j && *divisor != 0 && dividend % divisor == 0)
+                    .map(|(_, divisor)| dividend / divisor)
+            })
+            .next()
+    }
+}
+
+impl<F> RowReducer for F
+where
+    F: Fn(&[isize]) -> Option<isize>,
+{
+    fn reduce(&self, row: &[isize]) -> Option<isize> {
+        self(row)
+    }
+}
+
+/// Rows that a reducer could not find an answer for, numbered from 1 like
+/// `RaggedRow` and `NonNumericCell` rows.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnreducedRows(pub Vec<usize>);
+
+impl fmt::Display for UnreducedRows {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not reduce rows {:?}", self.0)
+    }
+}
+
+/// Anything that can go wrong when checksumming a spreadsheet given as text.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChecksumError {
+    NonNumericCells(Vec<NonNumericCell>),
+    Unreduced(UnreducedRows),
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ChecksumError::NonNumericCells(ref cells) => {
+                let descriptions: Vec<_> = cells.iter().map(|c| c.to_string()).collect();
+                write!(f, "{}", descriptions.join(", "))
+            }
+            &ChecksumError::Unreduced(ref unreduced) => write!(f, "{}", unreduced),
+        }
+    }
+}
+
+fn checksum(s: &str) -> Result<isize, ChecksumError> {
+    let sheet = parse_spreadsheet(s, None).map_err(ChecksumError::NonNumericCells)?;
+    matrix_checksum(&sheet.rows).map_err(ChecksumError::Unreduced)
+}
+
+fn evenly_divisible_checksum(s: &str) -> Result<isize, ChecksumError> {
+    let sheet = parse_spreadsheet(s, None).map_err(ChecksumError::NonNumericCells)?;
+    matrix_checksum_with(&sheet.rows, &EvenlyDivisibleQuotient).map_err(ChecksumError::Unreduced)
+}
+
+fn matrix_checksum(matrix: &Vec<Vec<isize>>) -> Result<isize, UnreducedRows> {
+    matrix_checksum_with(matrix, &Range)
+}
+
+/// Sums up what the reducer makes of each row; if any row could not be
+/// reduced, all such rows are reported instead.
+pub fn matrix_checksum_with<R>(
+    matrix: &Vec<Vec<isize>>,
+    reducer: &R,
+) -> Result<isize, UnreducedRows>
+where
+    R: RowReducer,
+{
+    let (sum, unreduced) = matrix.iter().enumerate().fold(
+        (0, Vec::new()),
+        |(sum, mut unreduced), (idx, row)| match reducer.reduce(row) {
+            Some(v) => (sum + v, unreduced),
+            None => {
+                unreduced.push(idx + 1);
+                (sum, unreduced)
+            }
+        },
+    );
+    if unreduced.is_empty() {
+        Ok(sum)
+    } else {
+        Err(UnreducedRows(unreduced))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Delimiter {
+    Tab,
+    Space,
+    Comma,
+    Semicolon,
+}
+
+// When auto-detecting, Space comes last because it often shows up as padding
+// around cells separated by one of the others.
+const DETECTABLE_DELIMITERS: [Delimiter; 4] = [
+    Delimiter::Tab,
+    Delimiter::Comma,
+    Delimiter::Semicolon,
+    Delimiter::Space,
+];
+
+impl Delimiter {
+    fn as_char(&self) -> char {
+        match self {
+            &Delimiter::Tab => '\t',
+            &Delimiter::Space => ' ',
+            &Delimiter::Comma => ',',
+            &Delimiter::Semicolon => ';',
+        }
+    }
+
+    /// Picks the delimiter used in the first non-blank line, falling back
+    /// to Tab for single-column sheets.
+    fn detect(s: &str) -> Delimiter {
+        let first_line = s.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+        DETECTABLE_DELIMITERS
+            .iter()
+            .find(|d| first_line.contains(d.as_char()))
+            .map(|d| *d)
+            .unwrap_or(Delimiter::Tab)
+    }
+
+    fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        if line.trim().is_empty() {
+            vec![]
+        } else if *self == Delimiter::Space {
+            // Runs of spaces are usually there to line columns up
+            line.split(' ').filter(|cell| !cell.is_empty()).collect()
+        } else {
+            line.split(self.as_char()).map(|cell| cell.trim()).collect()
+        }
+    }
+}
+
+/// A row whose number of cells differs from the first non-blank row's.
+///
+/// Rows and columns are numbered from 1, like lines in an editor.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RaggedRow {
+    pub row: usize,
+    pub expected: usize,
+    pub found: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct NonNumericCell {
+    pub row: usize,
+    pub column: usize,
+    pub cell: String,
+}
+
+impl fmt::Display for NonNumericCell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Non-numeric cell {:?} at row {}, column {}",
+            self.cell, self.row, self.column
+        )
+    }
+}
+
+/// A parsed spreadsheet. `rows[i]` comes from line `i + 1` of the input.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spreadsheet {
+    pub delimiter: Delimiter,
+    pub rows: Vec<Vec<isize>>,
+    pub ragged_rows: Vec<RaggedRow>,
+}
+
+/// Parses a spreadsheet, detecting the delimiter if none is given. Both LF and
+/// CRLF line endings are handled, and trailing blank lines are dropped.
+///
+/// Ragged rows are not an error (the puzzle's own example is ragged), but they
+/// are reported in the result. Blank lines before the last row are kept as
+/// empty rows, and are ragged wherever they are. Non-numeric cells are errors, and all of them
+/// are returned.
+pub fn parse_spreadsheet(
+    s: &str,
+    delimiter: Option<Delimiter>,
+) -> Result<Spreadsheet, Vec<NonNumericCell>> {
+    let delimiter = delimiter.unwrap_or_else(|| Delimiter::detect(s));
+    let mut lines: Vec<_> = s.lines().collect();
+    while lines.last().map(|l| l.trim().is_empty()).unwrap_or(false) {
+        lines.pop();
+    }
+    let mut rows = Vec::with_capacity(lines.len());
+    let mut ragged_rows = Vec::new();
+    let mut non_numeric_cells = Vec::new();
+    let mut expected_width = None;
+    let mut leading_blank_rows = Vec::new();
+    for (line_idx, line) in lines.iter().enumerate() {
+        let row_num = line_idx + 1;
+        let cells = delimiter.split(line);
+        match expected_width {
+            // Blank lines don't say anything about the width, so leading ones
+            // get reported once it's known, like any other blank line
+            None if cells.is_empty() => leading_blank_rows.push(row_num),
+            None => {
+                expected_width = Some(cells.len());
+                ragged_rows.extend(leading_blank_rows.drain(..).map(|row| RaggedRow {
+                    row,
+                    expected: cells.len(),
+                    found: 0,
+                }));
+            }
+            Some(expected) if expected != cells.len() => ragged_rows.push(RaggedRow {
+                row: row_num,
+                expected,
+                found: cells.len(),
+            }),
+            _ => (),
+        }
+        let mut row = Vec::with_capacity(cells.len());
+        for (cell_idx, cell) in cells.iter().enumerate() {
+            match cell.parse() {
+                Ok(v) => row.push(v),
+                Err(_) => non_numeric_cells.push(NonNumericCell {
+                    row: row_num,
+                    column: cell_idx + 1,
+                    cell: cell.to_string(),
+                }),
+            }
+        }
+        rows.push(row);
+    }
+    if non_numeric_cells.is_empty() {
+        Ok(Spreadsheet {
+            delimiter,
+            rows,
+            ragged_rows,
         })
-    });
-    max_mins
-        .filter_map(|maybe_max_min| maybe_max_min.map(|(min, max)| max - min))
-        .sum()
-}
-
-fn string_to_matrix(s: &str) -> Vec<Vec<isize>> {
-    s.split("\n")
-        .map(|v| {
-            v.split("\t")
-                .into_iter()
-                .filter_map(|s_i| s_i.parse().ok())
-                .collect()
-        })
-        .collect()
+    } else {
+        Err(non_numeric_cells)
+    }
 }
 
 #[cfg(test)]
@@ -49,20 +300,140 @@ mod tests {
     #[test]
     fn matrix_checksum_test() {
         let checksum = matrix_checksum(&vec![vec![5, 1, 9, 5], vec![7, 5, 3], vec![2, 4, 6, 8]]);
-        assert_eq!(checksum, 18);
+        assert_eq!(checksum, Ok(18));
+    }
+
+    #[test]
+    fn evenly_divisible_quotient_checksum_test() {
+        let matrix = vec![vec![5, 9, 2, 8], vec![9, 4, 7, 3], vec![3, 8, 6, 5]];
+        let checksum = matrix_checksum_with(&matrix, &EvenlyDivisibleQuotient);
+        assert_eq!(checksum, Ok(9));
+    }
+
+    #[test]
+    fn matrix_checksum_with_custom_reducer_test() {
+        let matrix = vec![vec![5, 1, 9, 5], vec![7, 5, 3]];
+        let first = |row: &[isize]| row.first().map(|v| *v);
+        assert_eq!(matrix_checksum_with(&matrix, &first), Ok(12));
     }
 
     #[test]
-    fn string_to_matrix_test() {
-        let parsed = string_to_matrix(
+    fn matrix_checksum_unreduced_rows_test() {
+        let matrix = vec![vec![5, 9, 2, 8], vec![], vec![7, 5, 3], vec![3, 8, 6, 5]];
+        assert_eq!(matrix_checksum(&matrix), Err(UnreducedRows(vec![2])));
+        assert_eq!(
+            matrix_checksum_with(&matrix, &EvenlyDivisibleQuotient),
+            Err(UnreducedRows(vec![2, 3]))
+        );
+    }
+
+    #[test]
+    fn parse_spreadsheet_test() {
+        let parsed = parse_spreadsheet(
             "5\t1\t9\t5
 7\t5\t3
 2\t4\t6\t8",
+            None,
+        ).unwrap();
+        assert_eq!(parsed.delimiter, Delimiter::Tab);
+        assert_eq!(
+            parsed.rows,
+            vec![vec![5, 1, 9, 5], vec![7, 5, 3], vec![2, 4, 6, 8]]
         );
+        assert_eq!(
+            parsed.ragged_rows,
+            vec![
+                RaggedRow {
+                    row: 2,
+                    expected: 4,
+                    found: 3,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_spreadsheet_delimiters_test() {
+        let expected = vec![vec![5, 1, 9], vec![7, -5, 3]];
+        let inputs = vec![
+            ("5 1  9\n 7 -5 3\n", Delimiter::Space),
+            ("5,1,9\r\n7, -5,3\r\n", Delimiter::Comma),
+            ("5;1;9\n7;-5;3", Delimiter::Semicolon),
+            ("5\t1\t9\r\n7\t-5\t3\r\n\r\n", Delimiter::Tab),
+        ];
+        for (input, delimiter) in inputs {
+            let detected = parse_spreadsheet(input, None).unwrap();
+            assert_eq!(detected.delimiter, delimiter);
+            assert_eq!(detected.rows, expected);
+            assert!(detected.ragged_rows.is_empty());
+            let given = parse_spreadsheet(input, Some(delimiter)).unwrap();
+            assert_eq!(given, detected);
+        }
+    }
+
+    #[test]
+    fn parse_spreadsheet_blank_lines_test() {
+        let parsed = parse_spreadsheet("\n\n5,1,9\n\n7,5,3\n", None).unwrap();
+        assert_eq!(
+            parsed.rows,
+            vec![vec![], vec![], vec![5, 1, 9], vec![], vec![7, 5, 3]]
+        );
+        let blank_rows: Vec<_> = parsed.ragged_rows.iter().map(|r| r.row).collect();
+        assert_eq!(blank_rows, vec![1, 2, 4]);
+        assert!(
+            parsed
+                .ragged_rows
+                .iter()
+                .all(|r| r.expected == 3 && r.found == 0)
+        );
+        assert_eq!(
+            evenly_divisible_checksum("\n5 9 2 8\n\n9 4 7 3"),
+            Err(ChecksumError::Unreduced(UnreducedRows(vec![1, 3])))
+        );
+    }
+
+    #[test]
+    fn parse_spreadsheet_non_numeric_test() {
+        let parsed = parse_spreadsheet("1,2,3\n4,x,6\n7,8,", None);
         assert_eq!(
             parsed,
-            vec![vec![5, 1, 9, 5], vec![7, 5, 3], vec![2, 4, 6, 8]]
-        )
+            Err(vec![
+                NonNumericCell {
+                    row: 2,
+                    column: 2,
+                    cell: "x".to_string(),
+                },
+                NonNumericCell {
+                    row: 3,
+                    column: 3,
+                    cell: "".to_string(),
+                },
+            ])
+        );
+        let wrong_delimiter = parse_spreadsheet("1,2\n3,4", Some(Delimiter::Tab));
+        assert_eq!(wrong_delimiter.unwrap_err().len(), 2);
     }
 
+    #[test]
+    fn checksum_errors_test() {
+        assert_eq!(checksum("5 1 9 5\n7 5 3\n2 4 6 8\n"), Ok(18));
+        assert_eq!(
+            checksum("5 1\n\n2 4"),
+            Err(ChecksumError::Unreduced(UnreducedRows(vec![2])))
+        );
+        assert_eq!(
+            checksum("\n5 1\n2 4").unwrap_err().to_string(),
+            "Could not reduce rows [1]"
+        );
+        assert!(match checksum("5 1\n2 four") {
+            Err(ChecksumError::NonNumericCells(ref cells)) => cells.len() == 1,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn checksum_real_test() {
+        assert_eq!(checksum(DAY_2_INPUT), Ok(32121));
+        assert_eq!(evenly_divisible_checksum(DAY_2_INPUT), Ok(197));
+    }
 }
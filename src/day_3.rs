@@ -1,9 +1,17 @@
+use std::collections::HashMap;
+
+use day_11::{HexAxialCoord, StepsDistance};
+
 const DAY_3_INPUT: u64 = 368078;
 
 pub fn run() -> Result<(), &'static str> {
     println!("*** Day 3: Spiral Memory ***");
     println!("Input: {}", DAY_3_INPUT);
-    println!("Solution: {}\n", steps_to_centre(DAY_3_INPUT)?);
+    println!("Solution 1: {}\n", steps_to_centre(DAY_3_INPUT)?);
+    println!(
+        "Solution 2: {}\n",
+        first_stress_test_value_larger_than(DAY_3_INPUT).ok_or("Ran out of values")?
+    );
     Ok(())
 }
 
@@ -13,57 +21,541 @@ fn steps_to_centre(idx: u64) -> Result<u64, &'static str> {
     Ok(total as u64)
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-struct Coords {
-    x: i64,
-    y: i64,
-}
-
-fn idx_to_coords(idx: u64) -> Result<Coords, &'static str> {
-    if idx > 0 {
-        let r = {
-            let idx_to_check = idx as i64;
-            let layer = (((idx as f64).sqrt() - 1f64) / 2f64).ceil() as i64;
-            let points_in_layer = 2 * layer + 1;
-            // this is the greatest index in the layer
-            let lower_right_idx = points_in_layer.pow(2);
-            let side_width = points_in_layer - 1;
-            if idx_to_check >= (lower_right_idx - side_width) {
-                // we are on the south-side of the layer
-                Coords {
-                    x: layer - (lower_right_idx - idx_to_check),
-                    y: -layer,
-                }
-            } else {
-                let lower_left_idx = lower_right_idx - side_width;
-                if idx_to_check >= (lower_left_idx - side_width) {
-                    Coords {
-                        x: -layer,
-                        y: -layer + (lower_left_idx - idx_to_check),
-                    }
-                } else {
-                    let upper_left_idx = lower_left_idx - side_width;
-                    if idx_to_check >= upper_left_idx - side_width {
-                        Coords {
-                            x: -layer + (upper_left_idx - idx_to_check),
-                            y: layer,
-                        }
-                    } else {
-                        let upper_right_idx = upper_left_idx - side_width;
-                        Coords {
-                            x: layer,
-                            y: layer - (upper_right_idx - idx_to_check),
-                        }
-                    }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Coords {
+    pub x: i64,
+    pub y: i64,
+}
+
+impl Coords {
+    fn neighbours(&self) -> Vec<Coords> {
+        let mut v = Vec::with_capacity(8);
+        for x in -1..2 {
+            for y in -1..2 {
+                if x != 0 || y != 0 {
+                    v.push(Coords {
+                        x: self.x + x,
+                        y: self.y + y,
+                    });
                 }
             }
+        }
+        v
+    }
+}
+
+/// Walks the spiral from the centre, yielding `(idx, coords, value)` where each
+/// value is the sum of the values already written to the neighbouring squares,
+/// starting with 1 in the centre.
+///
+/// Values grow quickly, so the iterator ends once the next one would overflow a u64.
+pub struct StressTestIter {
+    spiral: SpiralIter,
+    written: HashMap<Coords, u64>,
+}
+
+impl StressTestIter {
+    pub fn new() -> StressTestIter {
+        StressTestIter {
+            spiral: SpiralIter::new(),
+            written: HashMap::new(),
+        }
+    }
+}
+
+impl Iterator for StressTestIter {
+    type Item = (u64, Coords, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Only move the spiral along once we know the value fits
+        let mut spiral = self.spiral.clone();
+        let (idx, coords) = spiral.next()?;
+        let value = if idx == 1 {
+            Some(1)
+        } else {
+            coords.neighbours().iter().fold(Some(0), |acc, n| {
+                acc.and_then(|sum: u64| sum.checked_add(*self.written.get(n).unwrap_or(&0)))
+            })
+        }?;
+        self.written.insert(coords, value);
+        self.spiral = spiral;
+        Some((idx, coords, value))
+    }
+}
+
+/// Walks the spiral in index order, yielding `(idx, coords)`, one step at a time
+/// and without any square roots. Ends after `u64::max_value()`.
+#[derive(Clone, Debug)]
+pub struct SpiralIter {
+    next: Option<(u64, Coords)>,
+}
+
+impl SpiralIter {
+    pub fn new() -> SpiralIter {
+        SpiralIter {
+            next: Some((1, Coords { x: 0, y: 0 })),
+        }
+    }
+
+    pub fn starting_at(idx: u64) -> Result<SpiralIter, &'static str> {
+        let coords = idx_to_coords(idx)?;
+        Ok(SpiralIter {
+            next: Some((idx, coords)),
+        })
+    }
+}
+
+impl Iterator for SpiralIter {
+    type Item = (u64, Coords);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        let (idx, Coords { x, y }) = current;
+        let layer = x.abs().max(y.abs());
+        let next_coords = if x == layer && y == -layer {
+            // Lower right corner, which is where each layer ends
+            Coords { x: x + 1, y }
+        } else if x == layer && y < layer {
+            Coords { x, y: y + 1 }
+        } else if y == layer && x > -layer {
+            Coords { x: x - 1, y }
+        } else if x == -layer && y > -layer {
+            Coords { x, y: y - 1 }
+        } else {
+            Coords { x: x + 1, y }
+        };
+        self.next = idx.checked_add(1).map(|next_idx| (next_idx, next_coords));
+        Some(current)
+    }
+}
+
+fn first_stress_test_value_larger_than(input: u64) -> Option<u64> {
+    StressTestIter::new()
+        .map(|(_, _, value)| value)
+        .find(|value| *value > input)
+}
+
+/// Floor of the square root of n, exact for the whole u64 range (unlike going
+/// through f64 alone).
+fn isqrt(n: u64) -> u64 {
+    let mut r = (n as f64).sqrt() as u64;
+    while r.checked_mul(r).map(|sq| sq > n).unwrap_or(true) {
+        r -= 1;
+    }
+    while (r + 1).checked_mul(r + 1).map(|sq| sq <= n).unwrap_or(false) {
+        r += 1;
+    }
+    r
+}
+
+/// Works out where an index ends up on the spiral in O(1).
+///
+/// Layer k (the centre being layer 0) holds the indices after (2k - 1)^2, up to
+/// and including (2k + 1)^2. Positions are worked out relative to the start of
+/// the layer, so that nothing overflows, even for `u64::max_value()`.
+pub fn idx_to_coords(idx: u64) -> Result<Coords, &'static str> {
+    if idx > 1 {
+        let layer = (isqrt(idx - 1) + 1) / 2;
+        let previous_layer_end = (2 * layer - 1) * (2 * layer - 1);
+        let pos = (idx - previous_layer_end - 1) as i64;
+        let k = layer as i64;
+        let r = if pos < 2 * k {
+            // east side, going up
+            Coords {
+                x: k,
+                y: pos - k + 1,
+            }
+        } else if pos < 4 * k {
+            // north side, going left
+            Coords {
+                x: 3 * k - 1 - pos,
+                y: k,
+            }
+        } else if pos < 6 * k {
+            // west side, going down
+            Coords {
+                x: -k,
+                y: 5 * k - 1 - pos,
+            }
+        } else {
+            // south side, going right
+            Coords {
+                x: pos - 7 * k + 1,
+                y: -k,
+            }
         };
         Ok(r)
+    } else if idx == 1 {
+        Ok(Coords { x: 0, y: 0 })
     } else {
         Err("Idx must be greater than 0")
     }
 }
 
+/// Inverse of `idx_to_coords`, in O(1).
+///
+/// # Panics
+///
+/// Panics if the coordinates are so far out that their index does not fit in a
+/// u64; use `checked_coords_to_idx` if that can happen.
+pub fn coords_to_idx(coords: Coords) -> u64 {
+    checked_coords_to_idx(coords).expect("Coords are too far out to have a u64 index")
+}
+
+/// Inverse of `idx_to_coords`, returning None if the index would not fit in a u64.
+pub fn checked_coords_to_idx(coords: Coords) -> Option<u64> {
+    let Coords { x, y } = coords;
+    let layer = x.checked_abs()?.max(y.checked_abs()?) as u64;
+    if layer == 0 {
+        Some(1)
+    } else {
+        let previous_layer_end = {
+            let side = layer.checked_mul(2)? - 1;
+            side.checked_mul(side)?
+        };
+        let k = layer as i64;
+        // Same sides as in idx_to_coords; none of these can overflow since k
+        // is already known to be small enough for the square above.
+        let pos = if x == k && y > -k {
+            y + k - 1
+        } else if y == k {
+            3 * k - 1 - x
+        } else if x == -k {
+            5 * k - 1 - y
+        } else {
+            x + 7 * k - 1
+        };
+        previous_layer_end.checked_add(pos as u64 + 1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Heading {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Turn {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Spiral addressing schemes; indices start at 1 in the centre.
+pub trait Spiral {
+    type Coords;
+
+    fn idx_to_coords(&self, idx: u64) -> Result<Self::Coords, &'static str>;
+
+    /// None if the index of the coordinates would not fit in a u64.
+    fn coords_to_idx(&self, coords: Self::Coords) -> Option<u64>;
+
+    /// Fewest steps needed to get from one index to the other.
+    fn steps_between(&self, from: u64, to: u64) -> Result<u64, &'static str>;
+
+    /// Renders the indices in the window between the given columns and rows
+    /// (both inclusive), one text line per row with the highest row on top.
+    /// Squares that are too far out to have a u64 index are shown as '.'.
+    fn render_window(&self, columns: (i64, i64), rows: (i64, i64)) -> String;
+}
+
+/// Square spiral that takes its first step from the centre towards `heading`,
+/// and keeps turning `turn` from there on.
+///
+/// The puzzle's spiral is `PUZZLE_SPIRAL`; every other one is a rotation and/or
+/// reflection of it, which is how they are worked out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SquareSpiral {
+    pub heading: Heading,
+    pub turn: Turn,
+}
+
+pub const PUZZLE_SPIRAL: SquareSpiral = SquareSpiral {
+    heading: Heading::Right,
+    turn: Turn::CounterClockwise,
+};
+
+impl SquareSpiral {
+    fn from_puzzle_coords(&self, coords: Coords) -> Coords {
+        let Coords { x, y } = match self.turn {
+            Turn::CounterClockwise => coords,
+            Turn::Clockwise => Coords {
+                x: coords.x,
+                y: -coords.y,
+            },
+        };
+        match self.heading {
+            Heading::Right => Coords { x, y },
+            Heading::Up => Coords { x: -y, y: x },
+            Heading::Left => Coords { x: -x, y: -y },
+            Heading::Down => Coords { x: y, y: -x },
+        }
+    }
+
+    fn to_puzzle_coords(&self, coords: Coords) -> Option<Coords> {
+        let Coords { x, y } = coords;
+        let unrotated = match self.heading {
+            Heading::Right => Coords { x, y },
+            Heading::Up => Coords {
+                x: y,
+                y: x.checked_neg()?,
+            },
+            Heading::Left => Coords {
+                x: x.checked_neg()?,
+                y: y.checked_neg()?,
+            },
+            Heading::Down => Coords {
+                x: y.checked_neg()?,
+                y: x,
+            },
+        };
+        match self.turn {
+            Turn::CounterClockwise => Some(unrotated),
+            Turn::Clockwise => Some(Coords {
+                x: unrotated.x,
+                y: unrotated.y.checked_neg()?,
+            }),
+        }
+    }
+}
+
+impl Spiral for SquareSpiral {
+    type Coords = Coords;
+
+    fn idx_to_coords(&self, idx: u64) -> Result<Coords, &'static str> {
+        idx_to_coords(idx).map(|coords| self.from_puzzle_coords(coords))
+    }
+
+    fn coords_to_idx(&self, coords: Coords) -> Option<u64> {
+        self.to_puzzle_coords(coords)
+            .and_then(checked_coords_to_idx)
+    }
+
+    fn steps_between(&self, from: u64, to: u64) -> Result<u64, &'static str> {
+        let from_coords = self.idx_to_coords(from)?;
+        let to_coords = self.idx_to_coords(to)?;
+        let x_steps = (from_coords.x - to_coords.x).abs();
+        let y_steps = (from_coords.y - to_coords.y).abs();
+        Ok((x_steps + y_steps) as u64)
+    }
+
+    fn render_window(&self, columns: (i64, i64), rows: (i64, i64)) -> String {
+        let lines = inclusive_range(rows)
+            .into_iter()
+            .rev()
+            .map(|y| {
+                inclusive_range(columns)
+                    .into_iter()
+                    .map(|x| match self.coords_to_idx(Coords { x, y }) {
+                        Some(idx) => RenderedCell::Idx(idx),
+                        None => RenderedCell::OutOfRange,
+                    })
+                    .collect()
+            })
+            .collect();
+        render_lines(&lines)
+    }
+}
+
+/// Hexagonal spiral over day 11's axial coordinates.
+///
+/// Going counter-clockwise, ring k starts one step north-west of where ring
+/// k - 1 ended (due north of the centre), then runs around the ring to end due
+/// north of the centre again. Clockwise spirals are the mirror image of that,
+/// across the north-south axis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HexSpiral {
+    pub turn: Turn,
+}
+
+// For each side of a ring, going counter-clockwise from due north: where the
+// side starts (as a multiple of the ring number) and which way it runs.
+const HEX_RING_SIDES: [((isize, isize), (isize, isize)); 6] = [
+    ((0, 1), (-1, 0)),
+    ((-1, 1), (0, -1)),
+    ((-1, 0), (1, -1)),
+    ((0, -1), (1, 0)),
+    ((1, -1), (0, 1)),
+    ((1, 0), (-1, 1)),
+];
+
+/// Index of the first hex in ring k (k > 0), if it fits in a u64.
+fn hex_ring_start(k: u64) -> Option<u64> {
+    k.checked_mul(k - 1)
+        .and_then(|n| n.checked_mul(3))
+        .and_then(|n| n.checked_add(2))
+}
+
+/// Which ring the coordinates are on, if working that out doesn't overflow.
+fn hex_ring(coords: &HexAxialCoord) -> Option<isize> {
+    let s = coords.q.checked_add(coords.r)?;
+    Some(coords.q.checked_abs()?.max(coords.r.checked_abs()?).max(s.checked_abs()?))
+}
+
+impl HexSpiral {
+    fn mirror(&self, coords: HexAxialCoord) -> Option<HexAxialCoord> {
+        match self.turn {
+            Turn::CounterClockwise => Some(coords),
+            Turn::Clockwise => Some(HexAxialCoord {
+                q: coords.q.checked_neg()?,
+                r: coords.q.checked_add(coords.r)?,
+            }),
+        }
+    }
+}
+
+impl Spiral for HexSpiral {
+    type Coords = HexAxialCoord;
+
+    fn idx_to_coords(&self, idx: u64) -> Result<HexAxialCoord, &'static str> {
+        if idx > 1 {
+            // Estimate the ring, then fix up whatever f64 got wrong
+            let mut k = (((idx - 1) as f64 / 3f64).sqrt() + 0.5) as u64;
+            while hex_ring_start(k + 1).map(|start| start <= idx).unwrap_or(false) {
+                k += 1;
+            }
+            while k > 1 && hex_ring_start(k).map(|start| start > idx).unwrap_or(true) {
+                k -= 1;
+            }
+            let pos = idx - hex_ring_start(k).ok_or("Ring does not fit in a u64")?;
+            // Position on the ring, counting from due north
+            let on_ring = (pos + 1) % (6 * k);
+            let ((corner_q, corner_r), (step_q, step_r)) = HEX_RING_SIDES[(on_ring / k) as usize];
+            let steps = (on_ring % k) as isize;
+            let k = k as isize;
+            let coords = HexAxialCoord {
+                q: corner_q * k + step_q * steps,
+                r: corner_r * k + step_r * steps,
+            };
+            self.mirror(coords).ok_or("Coordinates do not fit in an isize")
+        } else if idx == 1 {
+            Ok(HexAxialCoord { q: 0, r: 0 })
+        } else {
+            Err("Idx must be greater than 0")
+        }
+    }
+
+    fn coords_to_idx(&self, coords: HexAxialCoord) -> Option<u64> {
+        let coords = self.mirror(coords)?;
+        let k = hex_ring(&coords)?;
+        if k == 0 {
+            Some(1)
+        } else {
+            // Ring k starts at 3k(k - 1) + 2, so if that fits in a u64 then k
+            // is below 2^32, and nothing below (at most 6 * k) overflows a
+            // 64 bit isize
+            let start = hex_ring_start(k as u64)?;
+            // Steps counter-clockwise from due north: `side * k` to the corner
+            // that starts the side the coordinates are on, plus `steps` along it
+            let on_ring = HEX_RING_SIDES
+                .iter()
+                .enumerate()
+                .filter_map(
+                |(side, &((corner_q, corner_r), (step_q, step_r)))| {
+                    let (diff_q, diff_r) = (coords.q - corner_q * k, coords.r - corner_r * k);
+                    let steps = if step_q != 0 {
+                        diff_q / step_q
+                    } else {
+                        diff_r / step_r
+                    };
+                    if 0 <= steps && steps < k && diff_q == step_q * steps
+                        && diff_r == step_r * steps
+                    {
+                        Some(side as isize * k + steps)
+                    } else {
+                        None
+                    }
+                })
+                .next()?;
+            // Due north is the last hex of the ring, so the first one is a
+            // step further round
+            let pos = (on_ring + 6 * k - 1) % (6 * k);
+            start.checked_add(pos as u64)
+        }
+    }
+
+    fn steps_between(&self, from: u64, to: u64) -> Result<u64, &'static str> {
+        let from_coords = self.idx_to_coords(from)?;
+        let to_coords = self.idx_to_coords(to)?;
+        Ok(from_coords.steps_from(&to_coords) as u64)
+    }
+
+    /// Columns are q; rows are "offset" rows, where odd columns are drawn half
+    /// a row higher than even ones, which takes two text lines per row.
+    fn render_window(&self, columns: (i64, i64), rows: (i64, i64)) -> String {
+        let mut lines = Vec::new();
+        for row in inclusive_range(rows).into_iter().rev() {
+            for &drawn_parity in [1, 0].iter() {
+                let line = inclusive_range(columns)
+                    .into_iter()
+                    .map(|q| {
+                        if (q % 2).abs() != drawn_parity {
+                            RenderedCell::Gap
+                        } else {
+                            // Rounds down, even for negative q
+                            let q_halved_down = q >> 1;
+                            let coords = row.checked_sub(q_halved_down).map(|r| HexAxialCoord {
+                                q: q as isize,
+                                r: r as isize,
+                            });
+                            match coords.and_then(|coords| self.coords_to_idx(coords)) {
+                                Some(idx) => RenderedCell::Idx(idx),
+                                None => RenderedCell::OutOfRange,
+                            }
+                        }
+                    })
+                    .collect();
+                lines.push(line);
+            }
+        }
+        render_lines(&lines)
+    }
+}
+
+/// All of min to max, without overflowing when max is i64::max_value().
+fn inclusive_range((min, max): (i64, i64)) -> Vec<i64> {
+    if min > max {
+        vec![]
+    } else {
+        (min..max).chain(Some(max)).collect()
+    }
+}
+
+enum RenderedCell {
+    Idx(u64),
+    OutOfRange,
+    Gap,
+}
+
+fn render_lines(lines: &Vec<Vec<RenderedCell>>) -> String {
+    let width = lines
+        .iter()
+        .flat_map(|line| line.iter())
+        .filter_map(|cell| match cell {
+            &RenderedCell::Idx(idx) => Some(idx.to_string().len()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(1);
+    lines.iter().fold(String::new(), |mut acc, line| {
+        let rendered: Vec<_> = line.iter()
+            .map(|cell| match cell {
+                &RenderedCell::Idx(idx) => format!("{:>width$}", idx, width = width),
+                &RenderedCell::OutOfRange => format!("{:>width$}", ".", width = width),
+                &RenderedCell::Gap => format!("{:>width$}", "", width = width),
+            })
+            .collect();
+        acc.push_str(&rendered.join(" "));
+        while acc.ends_with(' ') {
+            acc.pop();
+        }
+        acc.push('\n');
+        acc
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use day_3::*;
@@ -74,6 +566,242 @@ mod tests {
         assert_eq!(idx_to_coords(2).unwrap(), Coords { x: 1, y: 0 });
         assert_eq!(idx_to_coords(9).unwrap(), Coords { x: 1, y: -1 });
         assert_eq!(idx_to_coords(22).unwrap(), Coords { x: -1, y: -2 });
+        assert!(idx_to_coords(0).is_err());
+    }
+
+    #[test]
+    fn coords_to_idx_test() {
+        assert_eq!(coords_to_idx(Coords { x: 0, y: 0 }), 1);
+        assert_eq!(coords_to_idx(Coords { x: 1, y: 0 }), 2);
+        assert_eq!(coords_to_idx(Coords { x: 1, y: -1 }), 9);
+        assert_eq!(coords_to_idx(Coords { x: -1, y: -2 }), 22);
+        assert_eq!(coords_to_idx(Coords { x: 2, y: -2 }), 25);
+    }
+
+    #[test]
+    fn spiral_round_trip_test() {
+        for (idx, coords) in SpiralIter::new().take(5_000_000) {
+            assert_eq!(idx_to_coords(idx), Ok(coords));
+            assert_eq!(coords_to_idx(coords), idx);
+        }
+    }
+
+    #[test]
+    fn spiral_round_trip_near_u64_limits_test() {
+        let start = u64::max_value() - 100_000;
+        let walked: Vec<_> = SpiralIter::starting_at(start).unwrap().collect();
+        assert_eq!(walked.len(), 100_001);
+        assert_eq!(walked.last().map(|&(idx, _)| idx), Some(u64::max_value()));
+        for (idx, coords) in walked {
+            assert_eq!(idx_to_coords(idx), Ok(coords));
+            assert_eq!(coords_to_idx(coords), idx);
+        }
+        // The last layer only partly fits in a u64
+        let max_coords = idx_to_coords(u64::max_value()).unwrap();
+        let past_max = SpiralIter::starting_at(u64::max_value() - 1)
+            .unwrap()
+            .map(|(_, coords)| coords)
+            .last();
+        assert_eq!(past_max, Some(max_coords));
+        // u64::max_value() is on the north side, which is walked leftwards
+        let beyond = Coords {
+            x: max_coords.x - 1,
+            y: max_coords.y,
+        };
+        assert_eq!(checked_coords_to_idx(beyond), None);
+        let far_out = Coords {
+            x: i64::min_value(),
+            y: 0,
+        };
+        assert_eq!(checked_coords_to_idx(far_out), None);
+    }
+
+    fn all_square_spirals() -> Vec<SquareSpiral> {
+        let headings = vec![Heading::Up, Heading::Down, Heading::Left, Heading::Right];
+        let turns = vec![Turn::Clockwise, Turn::CounterClockwise];
+        headings
+            .iter()
+            .flat_map(|heading| {
+                turns.iter().map(move |turn| SquareSpiral {
+                    heading: *heading,
+                    turn: *turn,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn square_spiral_variants_test() {
+        let cw_down = SquareSpiral {
+            heading: Heading::Down,
+            turn: Turn::Clockwise,
+        };
+        assert_eq!(cw_down.idx_to_coords(2), Ok(Coords { x: 0, y: -1 }));
+        assert_eq!(cw_down.idx_to_coords(3), Ok(Coords { x: -1, y: -1 }));
+        assert_eq!(cw_down.idx_to_coords(4), Ok(Coords { x: -1, y: 0 }));
+        assert_eq!(cw_down.coords_to_idx(Coords { x: 1, y: 1 }), Some(7));
+        let cw_right = SquareSpiral {
+            heading: Heading::Right,
+            turn: Turn::Clockwise,
+        };
+        assert_eq!(cw_right.idx_to_coords(3), Ok(Coords { x: 1, y: -1 }));
+        for spiral in all_square_spirals() {
+            let walk: Vec<_> = (1..10_000)
+                .map(|idx| spiral.idx_to_coords(idx).unwrap())
+                .collect();
+            for (idx, coords) in walk.iter().enumerate() {
+                assert_eq!(spiral.coords_to_idx(*coords), Some(idx as u64 + 1));
+            }
+            for pair in walk.windows(2) {
+                assert_eq!((pair[0].x - pair[1].x).abs() + (pair[0].y - pair[1].y).abs(), 1);
+            }
+            assert_eq!(spiral.steps_between(1, 1024), Ok(31));
+            assert_eq!(spiral.steps_between(1024, 1), Ok(31));
+            assert_eq!(spiral.steps_between(2, 3), Ok(1));
+        }
+    }
+
+    #[test]
+    fn hex_spiral_test() {
+        let ccw = HexSpiral {
+            turn: Turn::CounterClockwise,
+        };
+        assert_eq!(ccw.idx_to_coords(2), Ok(HexAxialCoord { q: -1, r: 1 }));
+        assert_eq!(ccw.idx_to_coords(7), Ok(HexAxialCoord { q: 0, r: 1 }));
+        assert_eq!(ccw.idx_to_coords(8), Ok(HexAxialCoord { q: -1, r: 2 }));
+        assert_eq!(ccw.idx_to_coords(19), Ok(HexAxialCoord { q: 0, r: 2 }));
+        assert!(ccw.idx_to_coords(0).is_err());
+        let cw = HexSpiral {
+            turn: Turn::Clockwise,
+        };
+        assert_eq!(cw.idx_to_coords(2), Ok(HexAxialCoord { q: 1, r: 0 }));
+        for spiral in vec![ccw, cw] {
+            let walk: Vec<_> = (1..100_000)
+                .map(|idx| spiral.idx_to_coords(idx).unwrap())
+                .collect();
+            for (idx, coords) in walk.iter().enumerate() {
+                assert_eq!(spiral.coords_to_idx(*coords), Some(idx as u64 + 1));
+            }
+            for pair in walk.windows(2) {
+                assert_eq!(pair[0].steps_from(&pair[1]), 1);
+            }
+            // Ring k ends at 3k(k + 1) + 1
+            assert_eq!(spiral.steps_between(1, 7), Ok(1));
+            assert_eq!(spiral.steps_between(1, 8), Ok(2));
+            assert_eq!(spiral.steps_between(1, 3 * 9 * 10 + 1), Ok(9));
+            assert_eq!(spiral.steps_between(1, 3 * 9 * 10 + 2), Ok(10));
+        }
+        let near_max = ccw.idx_to_coords(u64::max_value()).unwrap();
+        assert_eq!(ccw.coords_to_idx(near_max), Some(u64::max_value()));
+        let beyond = HexAxialCoord {
+            q: near_max.q + 1,
+            r: near_max.r,
+        };
+        let beyond_idx = ccw.coords_to_idx(beyond);
+        // One step east of the last hex is the one before it on the same ring
+        assert_eq!(beyond_idx, Some(u64::max_value() - 1));
+    }
+
+    #[test]
+    fn hex_spiral_overflow_test() {
+        let (min, max) = (isize::min_value(), isize::max_value());
+        for &turn in [Turn::CounterClockwise, Turn::Clockwise].iter() {
+            let spiral = HexSpiral { turn };
+            for &(q, r) in [(1 << 61, 0), (0, 1 << 61), (min, 0), (max, max), (max, 1), (0, min)]
+                .iter()
+            {
+                assert_eq!(spiral.coords_to_idx(HexAxialCoord { q, r }), None);
+            }
+            let far = (i64::max_value() - 1, i64::max_value());
+            let near_min = (i64::min_value(), i64::min_value() + 1);
+            for &(columns, rows) in [(far, (0, 0)), (near_min, far), (far, near_min)].iter() {
+                let rendered = spiral.render_window(columns, rows);
+                assert!(rendered.chars().all(|c| c == '.' || c.is_whitespace()));
+                assert!(rendered.contains('.'));
+            }
+        }
+    }
+
+    #[test]
+    fn render_square_window_test() {
+        let rendered = PUZZLE_SPIRAL.render_window((-2, 2), (-2, 2));
+        assert_eq!(
+            rendered,
+            "17 16 15 14 13
+18  5  4  3 12
+19  6  1  2 11
+20  7  8  9 10
+21 22 23 24 25
+"
+        );
+        let cw_up = SquareSpiral {
+            heading: Heading::Up,
+            turn: Turn::Clockwise,
+        };
+        assert_eq!(cw_up.render_window((-1, 1), (0, 1)), "9 2 3\n8 1 4\n");
+        assert_eq!(PUZZLE_SPIRAL.render_window((1, 0), (0, 0)), "\n");
+        let far_out = i64::max_value();
+        assert_eq!(
+            PUZZLE_SPIRAL.render_window((far_out - 1, far_out), (0, 0)),
+            ". .\n"
+        );
+    }
+
+    #[test]
+    fn render_hex_window_test() {
+        let spiral = HexSpiral {
+            turn: Turn::CounterClockwise,
+        };
+        let rendered = spiral.render_window((-1, 1), (-1, 1));
+        assert_eq!(
+            rendered,
+            " 8    18
+    7
+ 2     6
+    1
+ 3     5
+    4
+"
+        );
+    }
+
+    #[test]
+    fn isqrt_test() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(8), 2);
+        assert_eq!(isqrt(9), 3);
+        assert_eq!(isqrt(u64::max_value()), 4294967295);
+        assert_eq!(isqrt(4294967295 * 4294967295 - 1), 4294967294);
+    }
+
+    #[test]
+    fn stress_test_iter_test() {
+        let values: Vec<_> = StressTestIter::new().map(|(_, _, v)| v).take(23).collect();
+        assert_eq!(
+            values,
+            vec![
+                1, 1, 2, 4, 5, 10, 11, 23, 25, 26, 54, 57, 59, 122, 133, 142, 147, 304, 330, 351,
+                362, 747, 806,
+            ]
+        );
+        let mut iter = StressTestIter::new().skip(1);
+        assert_eq!(iter.next(), Some((2, Coords { x: 1, y: 0 }, 1)));
+        assert_eq!(iter.next(), Some((3, Coords { x: 1, y: 1 }, 2)));
+    }
+
+    #[test]
+    fn stress_test_iter_overflow_test() {
+        let last = StressTestIter::new().last().unwrap();
+        assert!(last.2 > u64::max_value() / 100);
+    }
+
+    #[test]
+    fn first_stress_test_value_larger_than_test() {
+        assert_eq!(first_stress_test_value_larger_than(0), Some(1));
+        assert_eq!(first_stress_test_value_larger_than(1), Some(2));
+        assert_eq!(first_stress_test_value_larger_than(747), Some(806));
+        assert_eq!(first_stress_test_value_larger_than(DAY_3_INPUT), Some(369601));
+        assert_eq!(first_stress_test_value_larger_than(u64::max_value()), None);
     }
 
 }
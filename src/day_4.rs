@@ -1,4 +1,9 @@
+use common::SplitMix64;
 use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::hash::Hash;
+use std::io::Read;
+use std::path::Path;
 
 const DAY_4_INPUT: &str = include_str!("../data/day_4_input");
 const PASSPHRASE_SPLIT_WITH: &str = " ";
@@ -74,3 +79,514 @@ impl<'a> PassphrasesValidator<'a> for HashSet<Vec<(char, u64)>> {
         self.len() == s.len()
     }
 }
+
+/// Endless supply of random passphrases that pass both `are_valid_passphrases`
+/// and `are_valid_passphrases_annagram_free`.
+///
+/// Words are picked from a wordlist, where words that are anagrams of each other
+/// (including plain duplicates) are treated as one choice, so no passphrase can
+/// end up with two of them.
+pub struct PassphraseGenerator {
+    anagram_groups: Vec<Vec<String>>,
+    word_count: usize,
+    rng: SplitMix64,
+}
+
+impl PassphraseGenerator {
+    /// Builds a generator from a whitespace-separated wordlist.
+    pub fn new(
+        wordlist: &str,
+        word_count: usize,
+        seed: u64,
+    ) -> Result<PassphraseGenerator, String> {
+        let words: Vec<_> = wordlist.split_whitespace().collect();
+        let anagram_groups: Vec<Vec<String>> = group_words_by(&words, anagram_key)
+            .into_iter()
+            .map(|mut group| {
+                group.sort();
+                group.dedup();
+                group.iter().map(|w| w.to_string()).collect()
+            })
+            .collect();
+        if word_count == 0 {
+            Err("Passphrases need at least 1 word".to_string())
+        } else if anagram_groups.len() < word_count {
+            Err(format!(
+                "Wordlist only has {} usable words, but {} are needed per passphrase",
+                anagram_groups.len(),
+                word_count
+            ))
+        } else {
+            Ok(PassphraseGenerator {
+                anagram_groups,
+                word_count,
+                rng: SplitMix64(seed),
+            })
+        }
+    }
+
+    pub fn from_file<P>(
+        path: P,
+        word_count: usize,
+        seed: u64,
+    ) -> Result<PassphraseGenerator, String>
+    where
+        P: AsRef<Path>,
+    {
+        let mut wordlist = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut wordlist))
+            .map_err(|e| format!("Could not read wordlist: {}", e))?;
+        PassphraseGenerator::new(&wordlist, word_count, seed)
+    }
+}
+
+impl Iterator for PassphraseGenerator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        // Partial Fisher-Yates: the first word_count groups end up as a random
+        // pick without replacement.
+        let groups_len = self.anagram_groups.len();
+        for i in 0..self.word_count {
+            let j = i + self.rng.below(groups_len - i);
+            self.anagram_groups.swap(i, j);
+        }
+        let mut words = Vec::with_capacity(self.word_count);
+        for group in self.anagram_groups.iter().take(self.word_count) {
+            let pick = self.rng.below(group.len());
+            words.push(group[pick].as_str());
+        }
+        Some(words.join(PASSPHRASE_SPLIT_WITH))
+    }
+}
+
+/// A single rule that passphrases can be checked against.
+///
+/// Each violation is reported as the words that broke the rule; rules about the
+/// passphrase as a whole, like `MinWordCount`, report no words.
+pub trait PassphrasePolicy {
+    fn describe(&self) -> String;
+    fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>>;
+}
+
+/// No word may appear more than once (part 1).
+pub struct NoDuplicates;
+
+/// No two words may be anagrams of each other (part 2).
+pub struct NoAnagrams;
+
+pub struct MinWordLength(pub usize);
+
+pub struct MinWordCount(pub usize);
+
+/// Every two words must be at least `min_edit_distance` (Levenshtein) edits
+/// apart.
+pub struct SimilarWords {
+    pub min_edit_distance: usize,
+}
+
+pub struct BannedWords(pub HashSet<String>);
+
+impl BannedWords {
+    pub fn new<'a, I>(words: I) -> BannedWords
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        BannedWords(words.into_iter().map(|w| w.to_string()).collect())
+    }
+}
+
+impl PassphrasePolicy for NoDuplicates {
+    fn describe(&self) -> String {
+        "no duplicate words".to_string()
+    }
+
+    fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>> {
+        group_words_by(words, |w| w.to_string())
+            .into_iter()
+            .filter(|group| group.len() > 1)
+            .collect()
+    }
+}
+
+impl PassphrasePolicy for NoAnagrams {
+    fn describe(&self) -> String {
+        "no anagrams".to_string()
+    }
+
+    fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>> {
+        group_words_by(words, anagram_key)
+            .into_iter()
+            .filter(|group| group.len() > 1)
+            .collect()
+    }
+}
+
+impl PassphrasePolicy for MinWordLength {
+    fn describe(&self) -> String {
+        format!("words must be at least {} characters long", self.0)
+    }
+
+    fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>> {
+        let too_short: Vec<_> = words
+            .iter()
+            .filter(|w| w.chars().count() < self.0)
+            .map(|w| *w)
+            .collect();
+        if too_short.is_empty() {
+            vec![]
+        } else {
+            vec![too_short]
+        }
+    }
+}
+
+impl PassphrasePolicy for MinWordCount {
+    fn describe(&self) -> String {
+        format!("at least {} words", self.0)
+    }
+
+    fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>> {
+        if words.len() < self.0 {
+            vec![vec![]]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl PassphrasePolicy for SimilarWords {
+    fn describe(&self) -> String {
+        let edits = if self.min_edit_distance == 1 { "edit" } else { "edits" };
+        format!(
+            "words must be at least {} {} apart",
+            self.min_edit_distance, edits
+        )
+    }
+
+    fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>> {
+        let mut v = Vec::new();
+        for (idx, w1) in words.iter().enumerate() {
+            for w2 in words.iter().skip(idx + 1) {
+                if edit_distance(w1, w2) < self.min_edit_distance {
+                    v.push(vec![*w1, *w2]);
+                }
+            }
+        }
+        v
+    }
+}
+
+impl PassphrasePolicy for BannedWords {
+    fn describe(&self) -> String {
+        "no banned words".to_string()
+    }
+
+    fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>> {
+        let banned: Vec<_> = words
+            .iter()
+            .filter(|w| self.0.contains(**w))
+            .map(|w| *w)
+            .collect();
+        if banned.is_empty() {
+            vec![]
+        } else {
+            vec![banned]
+        }
+    }
+}
+
+fn anagram_key(w: &str) -> Vec<char> {
+    let mut chars: Vec<_> = w.chars().collect();
+    chars.sort();
+    chars
+}
+
+/// Groups words that share the same key, in order of first appearance.
+fn group_words_by<'a, K, F>(words: &[&'a str], key: F) -> Vec<Vec<&'a str>>
+where
+    K: Eq + Hash + Clone,
+    F: Fn(&str) -> K,
+{
+    let mut keys_in_order = Vec::new();
+    let mut groups: HashMap<K, Vec<&'a str>> = HashMap::new();
+    for w in words.iter() {
+        let k = key(w);
+        if !groups.contains_key(&k) {
+            keys_in_order.push(k.clone());
+        }
+        groups.entry(k).or_insert_with(Vec::new).push(*w);
+    }
+    keys_in_order
+        .into_iter()
+        .filter_map(|k| groups.remove(&k))
+        .collect()
+}
+
+/// Levenshtein distance between two words, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<_> = b.chars().collect();
+    let mut previous_row: Vec<usize> = (0..b_chars.len() + 1).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current_row = Vec::with_capacity(b_chars.len() + 1);
+        current_row.push(i + 1);
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let substitution = previous_row[j] + if a_char == *b_char { 0 } else { 1 };
+            let insertion = current_row[j] + 1;
+            let deletion = previous_row[j + 1] + 1;
+            current_row.push(substitution.min(insertion).min(deletion));
+        }
+        previous_row = current_row;
+    }
+    previous_row[b_chars.len()]
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Violation {
+    pub rule: String,
+    pub words: Vec<String>,
+}
+
+/// Result of checking one line of passphrases. Lines are numbered from 1.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LineReport {
+    pub line: usize,
+    pub passphrase: String,
+    pub violations: Vec<Violation>,
+}
+
+impl LineReport {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// A stack of policies that every passphrase has to satisfy.
+pub struct PolicyStack {
+    policies: Vec<Box<PassphrasePolicy>>,
+}
+
+impl PolicyStack {
+    pub fn new() -> PolicyStack {
+        PolicyStack {
+            policies: Vec::new(),
+        }
+    }
+
+    pub fn with<P>(mut self, policy: P) -> PolicyStack
+    where
+        P: PassphrasePolicy + 'static,
+    {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    /// Every violation in a passphrase, in the order the policies were added.
+    pub fn check(&self, passphrase: &str) -> Vec<Violation> {
+        let words: Vec<_> = passphrase.split_whitespace().collect();
+        self.policies
+            .iter()
+            .flat_map(|policy| {
+                let rule = policy.describe();
+                policy
+                    .violations(&words)
+                    .into_iter()
+                    .map(move |offending| Violation {
+                        rule: rule.clone(),
+                        words: offending.iter().map(|w| w.to_string()).collect(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Reports on each non-blank line of passphrases.
+    pub fn report(&self, passphrases: &str) -> Vec<LineReport> {
+        passphrases
+            .lines()
+            .enumerate()
+            .filter(|&(_, l)| !l.trim().is_empty())
+            .map(|(idx, l)| LineReport {
+                line: idx + 1,
+                passphrase: l.trim().to_string(),
+                violations: self.check(l),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use day_4::*;
+
+    #[test]
+    fn edit_distance_test() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("flaw", "lawn"), 2);
+    }
+
+    #[test]
+    fn puzzle_policies_test() {
+        let part_1 = PolicyStack::new().with(NoDuplicates);
+        assert!(part_1.check("aa bb cc dd ee").is_empty());
+        assert_eq!(
+            part_1.check("aa bb cc dd aa"),
+            vec![
+                Violation {
+                    rule: "no duplicate words".to_string(),
+                    words: vec!["aa".to_string(), "aa".to_string()],
+                },
+            ]
+        );
+        assert!(part_1.check("aa bb cc dd aaa").is_empty());
+        let part_2 = PolicyStack::new().with(NoAnagrams);
+        assert!(part_2.check("abcde fghij").is_empty());
+        assert_eq!(part_2.check("abcde xyz ecdab")[0].words, vec!["abcde", "ecdab"]);
+        assert!(part_2.check("a ab abc abd abf abj").is_empty());
+        assert!(part_2.check("iiii oiii ooii oooi oooo").is_empty());
+        assert_eq!(part_2.check("oiii ioii iioi iiio")[0].words.len(), 4);
+    }
+
+    #[test]
+    fn puzzle_policies_agree_with_validators_test() {
+        let part_1 = PolicyStack::new().with(NoDuplicates);
+        let part_2 = PolicyStack::new().with(NoAnagrams);
+        for line in DAY_4_INPUT.trim().split("\n") {
+            assert_eq!(part_1.check(line).is_empty(), are_valid_passphrases(line));
+            assert_eq!(
+                part_2.check(line).is_empty(),
+                are_valid_passphrases_annagram_free(line)
+            );
+        }
+    }
+
+    #[test]
+    fn similar_words_test() {
+        let one_apart = SimilarWords {
+            min_edit_distance: 1,
+        };
+        assert_eq!(one_apart.describe(), "words must be at least 1 edit apart");
+        assert_eq!(one_apart.violations(&["abc", "abd", "abc"]), vec![vec!["abc", "abc"]]);
+        let three_apart = SimilarWords {
+            min_edit_distance: 3,
+        };
+        assert_eq!(
+            three_apart.violations(&["abc", "xyz", "abxy"]),
+            vec![vec!["abc", "abxy"]]
+        );
+    }
+
+    #[test]
+    fn policy_stack_report_test() {
+        let policies = PolicyStack::new()
+            .with(NoDuplicates)
+            .with(MinWordLength(3))
+            .with(MinWordCount(3))
+            .with(SimilarWords {
+                min_edit_distance: 2,
+            })
+            .with(BannedWords::new(vec!["password", "letmein"]));
+        let reports = policies.report(
+            "correct horse battery staple
+ok password
+
+horse house mouse horse",
+        );
+        assert_eq!(reports.len(), 3);
+        assert!(reports[0].is_valid());
+        assert_eq!(reports[1].line, 2);
+        let broken_rules: Vec<_> = reports[1]
+            .violations
+            .iter()
+            .map(|v| (v.rule.as_str(), v.words.clone()))
+            .collect();
+        assert_eq!(
+            broken_rules,
+            vec![
+                (
+                    "words must be at least 3 characters long",
+                    vec!["ok".to_string()],
+                ),
+                ("at least 3 words", vec![]),
+                ("no banned words", vec!["password".to_string()]),
+            ]
+        );
+        assert_eq!(reports[2].line, 4);
+        let similar: Vec<_> = reports[2]
+            .violations
+            .iter()
+            .filter(|v| v.rule == "words must be at least 2 edits apart")
+            .map(|v| v.words.join(" "))
+            .collect();
+        assert_eq!(
+            similar,
+            vec!["horse house", "horse horse", "house mouse", "house horse"]
+        );
+    }
+
+    #[test]
+    fn passphrase_generator_test() {
+        let wordlist = "listen silent enlist apple apple banana cherry\ndate\telder fig grape";
+        let generated: Vec<_> = PassphraseGenerator::new(wordlist, 5, 42)
+            .unwrap()
+            .take(2000)
+            .collect();
+        for phrase in generated.iter() {
+            assert_eq!(phrase.split(" ").count(), 5);
+            assert!(are_valid_passphrases(phrase), "{}", phrase);
+            assert!(are_valid_passphrases_annagram_free(phrase), "{}", phrase);
+        }
+        let again: Vec<_> = PassphraseGenerator::new(wordlist, 5, 42)
+            .unwrap()
+            .take(2000)
+            .collect();
+        assert_eq!(generated, again);
+        let other_seed: Vec<_> = PassphraseGenerator::new(wordlist, 5, 43)
+            .unwrap()
+            .take(2000)
+            .collect();
+        assert_ne!(generated, other_seed);
+        // Every usable word shows up eventually
+        for word in wordlist.split_whitespace() {
+            assert!(generated.iter().any(|p| p.split(" ").any(|w| w == word)));
+        }
+    }
+
+    #[test]
+    fn passphrase_generator_errors_test() {
+        assert!(PassphraseGenerator::new("abc bca cab cba", 2, 1).is_err());
+        assert!(PassphraseGenerator::new("abc def", 0, 1).is_err());
+        assert!(PassphraseGenerator::new("abc def", 2, 1).is_ok());
+        assert!(PassphraseGenerator::from_file("/does/not/exist", 2, 1).is_err());
+    }
+
+    #[test]
+    fn passphrase_generator_real_words_test() {
+        let generator = PassphraseGenerator::new(DAY_4_INPUT, 8, 2017).unwrap();
+        for phrase in generator.take(1000) {
+            assert!(are_valid_passphrases(&phrase));
+            assert!(are_valid_passphrases_annagram_free(&phrase));
+        }
+    }
+
+    #[test]
+    fn custom_policy_test() {
+        struct NoDigits;
+        impl PassphrasePolicy for NoDigits {
+            fn describe(&self) -> String {
+                "no digits".to_string()
+            }
+            fn violations<'a>(&self, words: &[&'a str]) -> Vec<Vec<&'a str>> {
+                words
+                    .iter()
+                    .filter(|w| w.chars().any(|c| c.is_digit(10)))
+                    .map(|w| vec![*w])
+                    .collect()
+            }
+        }
+        let policies = PolicyStack::new().with(NoDigits);
+        assert_eq!(policies.check("abc d3f g4i").len(), 2);
+    }
+}
@@ -1,3 +1,7 @@
+use common::{transient_len, CycleDetector};
+use std::io::Write;
+use std::io;
+
 const DAY_5_INPUT: &str = include_str!("../data/day_5_input");
 const SPLIT_ON: &str = "\n";
 
@@ -16,7 +20,16 @@ fn steps_to_escape(inst_str: &str) -> Result<u64, &'static str> {
 
 fn steps_to_escape_next(inst_str: &str) -> Result<u64, &'static str> {
     let parsed = instructions_str_to_vec(inst_str);
-    steps_to_escape_vec_inner(&parsed, |j| if j >= 3 { j - 1 } else { j + 1 })
+    escape_strange_jumps(&parsed).map(|state| state.steps)
+}
+
+/// The bump rule for part 2.
+fn strange_bump(j: i64) -> i64 {
+    if j >= 3 {
+        j - 1
+    } else {
+        j + 1
+    }
 }
 
 fn instructions_str_to_vec(inst_str: &str) -> Vec<i64> {
@@ -33,30 +46,381 @@ fn steps_to_escape_vec_inner<F>(
 where
     F: Fn(i64) -> i64,
 {
-    let mut instructions_scratchpad = instructions.clone();
+    match run_jumps(instructions, bump_jump_with, u64::max_value())? {
+        JumpOutcome::Escaped(state) => Ok(state.steps),
+        JumpOutcome::BudgetExhausted(_) => Err("Ran out of steps before escaping"),
+        JumpOutcome::Looping { .. } => Err("Instructions loop forever"),
+    }
+}
+
+/// Lists up to this long get checked for infinite loops, by comparing (index,
+/// offsets) states against checkpoints; for longer lists comparing the whole
+/// list on every step gets too expensive.
+const LOOP_DETECTION_MAX_LEN: usize = 32;
+
+/// Where a run of jumps got to; `idx` is where the next jump would be made
+/// from, which is outside of the list once escaped.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JumpState {
+    pub steps: u64,
+    pub idx: i64,
+    pub offsets: Vec<i64>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum JumpOutcome {
+    Escaped(JumpState),
+    BudgetExhausted(JumpState),
+    /// The state after `loop_start` steps comes back every `loop_length` steps,
+    /// so this will never escape. `state` is where that was noticed, within
+    /// `3 * (loop_start + loop_length)` steps.
+    Looping {
+        state: JumpState,
+        loop_start: u64,
+        loop_length: u64,
+    },
+}
+
+/// Jumps around the instructions, bumping each offset after jumping from it,
+/// until escaping or having taken `max_steps` steps.
+///
+/// Short lists (see `LOOP_DETECTION_MAX_LEN`) are also checked for states that
+/// repeat, which means they are stuck in a loop, using `common::CycleDetector`.
+/// A loop only gets reported if that is noticed within `max_steps` steps.
+pub fn run_jumps<F>(
+    instructions: &Vec<i64>,
+    bump_jump_with: F,
+    max_steps: u64,
+) -> Result<JumpOutcome, &'static str>
+where
+    F: Fn(i64) -> i64,
+{
+    run_jumps_observed(instructions, bump_jump_with, max_steps, |_| ())
+}
+
+/// `run_jumps`, calling `observe` with every jump as it is made.
+fn run_jumps_observed<F, O>(
+    instructions: &Vec<i64>,
+    bump_jump_with: F,
+    max_steps: u64,
+    mut observe: O,
+) -> Result<JumpOutcome, &'static str>
+where
+    F: Fn(i64) -> i64,
+    O: FnMut(Jump) -> (),
+{
     let instructions_length = instructions.len();
     if instructions_length == 0 {
         Err("Can't escape a zero-length instructions list")
     } else {
+        // (index, offsets) after each step
+        let mut current = (0, instructions.clone());
+        let mut loop_detector = if instructions_length <= LOOP_DETECTION_MAX_LEN {
+            Some(CycleDetector::new(current.clone()))
+        } else {
+            None
+        };
         let mut steps_taken: u64 = 0;
-        let mut current_idx: i64 = 0;
-        let mut escaped = false;
-        while !escaped && steps_taken <= u64::max_value() {
-            steps_taken += 1;
-            let next_jump = instructions_scratchpad[current_idx as usize];
-            let jump_bump = bump_jump_with(next_jump);
-            instructions_scratchpad[current_idx as usize] = jump_bump;
-            current_idx = current_idx + next_jump;
+        loop {
+            let (current_idx, _) = current;
             if current_idx < 0 || current_idx >= instructions_length as i64 {
-                escaped = true;
+                let (idx, offsets) = current;
+                return Ok(JumpOutcome::Escaped(JumpState {
+                    steps: steps_taken,
+                    idx,
+                    offsets,
+                }));
+            }
+            if steps_taken >= max_steps {
+                let (idx, offsets) = current;
+                return Ok(JumpOutcome::BudgetExhausted(JumpState {
+                    steps: steps_taken,
+                    idx,
+                    offsets,
+                }));
+            }
+            steps_taken += 1;
+            observe(Jump {
+                step: steps_taken,
+                from: current_idx as usize,
+                offset: current.1[current_idx as usize],
+            });
+            current.0 = jump(&mut current.1, current_idx, &bump_jump_with);
+            let loop_length = match loop_detector {
+                Some(ref mut detector) => detector.push(&current),
+                None => None,
+            };
+            if let Some(loop_length) = loop_length {
+                let step = |&(idx, ref offsets): &(i64, Vec<i64>)| {
+                    let mut next = offsets.clone();
+                    (jump(&mut next, idx, &bump_jump_with), next)
+                };
+                let loop_start = transient_len((0, instructions.clone()), step, loop_length);
+                let (idx, offsets) = current;
+                return Ok(JumpOutcome::Looping {
+                    state: JumpState {
+                        steps: steps_taken,
+                        idx,
+                        offsets,
+                    },
+                    loop_start: loop_start as u64,
+                    loop_length: loop_length as u64,
+                });
+            }
+        }
+    }
+}
+
+/// Makes the jump from `idx` and bumps its offset, returning where it lands.
+fn jump<F>(offsets: &mut Vec<i64>, idx: i64, bump_jump_with: &F) -> i64
+where
+    F: Fn(i64) -> i64,
+{
+    let next_jump = offsets[idx as usize];
+    offsets[idx as usize] = bump_jump_with(next_jump);
+    // Jumping past i64's limits is as good as escaping
+    idx.checked_add(next_jump).unwrap_or(-1)
+}
+
+// Settled offsets are kept as bits, this many to a u16: 1 for 3, 0 for 2.
+const SETTLED_CHUNK_LEN: usize = 16;
+const SETTLED_CHUNK_STATES: usize = 1 << SETTLED_CHUNK_LEN;
+// Jumps are at most 3 long, so the pointer enters all but the first settled
+// chunk it runs through at one of these positions.
+const SETTLED_CHUNK_ENTRIES: usize = 3;
+
+lazy_static! {
+    /// Every possible run through a settled chunk entered at one of its first
+    /// few positions, indexed by `entry_pos * SETTLED_CHUNK_STATES + bits`.
+    static ref SETTLED_CHUNK_RUNS: Vec<SettledChunkRun> = settled_chunk_runs();
+}
+
+/// Result of jumping into a chunk of settled offsets and running until leaving
+/// it, which is always forwards.
+#[derive(Clone, Copy)]
+struct SettledChunkRun {
+    bits_after: u16,
+    // Relative to the end of the chunk, so below SETTLED_CHUNK_ENTRIES
+    exit_pos: u8,
+    steps: u8,
+}
+
+/// Runs through the settled chunk `bits` from `pos` until leaving it.
+fn run_settled_chunk(bits: &mut u16, mut pos: usize) -> SettledChunkRun {
+    let mut steps = 0;
+    while pos < SETTLED_CHUNK_LEN {
+        let is_3 = *bits & (1 << pos) != 0;
+        // 3 becomes 2 and 2 becomes 3
+        *bits ^= 1 << pos;
+        pos += if is_3 { 3 } else { 2 };
+        steps += 1;
+    }
+    SettledChunkRun {
+        bits_after: *bits,
+        exit_pos: (pos - SETTLED_CHUNK_LEN) as u8,
+        steps,
+    }
+}
+
+fn settled_chunk_runs() -> Vec<SettledChunkRun> {
+    let mut runs = Vec::with_capacity(SETTLED_CHUNK_ENTRIES * SETTLED_CHUNK_STATES);
+    for entry_pos in 0..SETTLED_CHUNK_ENTRIES {
+        for bits in 0..SETTLED_CHUNK_STATES {
+            runs.push(run_settled_chunk(&mut (bits as u16), entry_pos));
+        }
+    }
+    runs
+}
+
+fn is_settled(offset: i16) -> bool {
+    offset == 2 || offset == 3
+}
+
+/// Same as `run_jumps` with part 2's bump rule and no step budget (which it
+/// doesn't need: every offset ends up at 2 or 3, so escaping is guaranteed),
+/// only much faster.
+///
+/// Offsets are stored as i16s, falling back to `run_jumps` for lists that don't
+/// fit. Under this rule, an offset that has reached 2 or 3 flips between the two
+/// forever, and the pointer only moves forwards through such offsets. Once the
+/// leading chunks of the list have all settled, they are kept as bits and the
+/// pointer gets moved through them a chunk at a time, using a lookup table.
+pub fn escape_strange_jumps(instructions: &Vec<i64>) -> Result<JumpState, &'static str> {
+    let fits_in_i16 = instructions
+        .iter()
+        .all(|j| *j >= i16::min_value() as i64 && *j <= i16::max_value() as i64);
+    if instructions.is_empty() || !fits_in_i16 {
+        return match run_jumps(instructions, strange_bump, u64::max_value())? {
+            JumpOutcome::Escaped(state) => Ok(state),
+            _ => Err("Ran out of steps before escaping"),
+        };
+    }
+    let len = instructions.len();
+    let mut offsets: Vec<i16> = instructions.iter().map(|j| *j as i16).collect();
+    let full_chunks = len / SETTLED_CHUNK_LEN;
+    let mut unsettled_in_chunk: Vec<usize> = offsets
+        .chunks(SETTLED_CHUNK_LEN)
+        .take(full_chunks)
+        .map(|chunk| chunk.iter().filter(|j| !is_settled(**j)).count())
+        .collect();
+    let mut settled_bits: Vec<u16> = Vec::with_capacity(full_chunks);
+    let runs: &Vec<SettledChunkRun> = &SETTLED_CHUNK_RUNS;
+
+    let settle_leading_chunks = |offsets: &Vec<i16>,
+                                 unsettled_in_chunk: &Vec<usize>,
+                                 settled_bits: &mut Vec<u16>| {
+        while settled_bits.len() < full_chunks && unsettled_in_chunk[settled_bits.len()] == 0 {
+            let chunk_start = settled_bits.len() * SETTLED_CHUNK_LEN;
+            let bits = offsets[chunk_start..chunk_start + SETTLED_CHUNK_LEN]
+                .iter()
+                .enumerate()
+                .fold(0u16, |acc, (i, j)| if *j == 3 { acc | (1 << i) } else { acc });
+            settled_bits.push(bits);
+        }
+    };
+    settle_leading_chunks(&offsets, &unsettled_in_chunk, &mut settled_bits);
+
+    let mut steps_taken: u64 = 0;
+    let mut current_idx: i64 = 0;
+    while current_idx >= 0 && current_idx < len as i64 {
+        let idx = current_idx as usize;
+        let settled_len = settled_bits.len() * SETTLED_CHUNK_LEN;
+        if idx < settled_len {
+            let mut chunk = idx / SETTLED_CHUNK_LEN;
+            let first = run_settled_chunk(&mut settled_bits[chunk], idx % SETTLED_CHUNK_LEN);
+            steps_taken += first.steps as u64;
+            let mut entry_pos = first.exit_pos as usize;
+            chunk += 1;
+            while chunk < settled_bits.len() {
+                let run = runs[entry_pos * SETTLED_CHUNK_STATES + settled_bits[chunk] as usize];
+                settled_bits[chunk] = run.bits_after;
+                steps_taken += run.steps as u64;
+                entry_pos = run.exit_pos as usize;
+                chunk += 1;
             }
+            current_idx = (chunk * SETTLED_CHUNK_LEN + entry_pos) as i64;
+        } else {
+            let next_jump = offsets[idx];
+            let bumped = if next_jump >= 3 {
+                next_jump - 1
+            } else {
+                next_jump + 1
+            };
+            offsets[idx] = bumped;
+            steps_taken += 1;
+            let chunk = idx / SETTLED_CHUNK_LEN;
+            if chunk < full_chunks && !is_settled(next_jump) && is_settled(bumped) {
+                unsettled_in_chunk[chunk] -= 1;
+                settle_leading_chunks(&offsets, &unsettled_in_chunk, &mut settled_bits);
+            }
+            current_idx += next_jump as i64;
+        }
+    }
+
+    // Unpack the settled chunks again
+    for (chunk, bits) in settled_bits.iter().enumerate() {
+        for i in 0..SETTLED_CHUNK_LEN {
+            offsets[chunk * SETTLED_CHUNK_LEN + i] = if bits & (1 << i) != 0 { 3 } else { 2 };
+        }
+    }
+    Ok(JumpState {
+        steps: steps_taken,
+        idx: current_idx,
+        offsets: offsets.iter().map(|j| *j as i64).collect(),
+    })
+}
+
+/// A single jump; `step` counts from 1.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Jump {
+    pub step: u64,
+    pub from: usize,
+    pub offset: i64,
+}
+
+/// What happened during a run of jumps, for working out why some lists take
+/// so long to escape.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JumpTrace {
+    pub outcome: JumpOutcome,
+    pub initial_offsets: Vec<i64>,
+    /// How many times a jump was made from each index.
+    pub visits: Vec<u64>,
+    /// The first of the longest jumps forwards, if any.
+    pub longest_forward_jump: Option<Jump>,
+    /// The first of the longest jumps backwards, if any.
+    pub longest_backward_jump: Option<Jump>,
+}
+
+/// `run_jumps`, but keeping track of everything in `JumpTrace` on the way.
+pub fn trace_jumps<F>(
+    instructions: &Vec<i64>,
+    bump_jump_with: F,
+    max_steps: u64,
+) -> Result<JumpTrace, &'static str>
+where
+    F: Fn(i64) -> i64,
+{
+    let mut visits = vec![0; instructions.len()];
+    let mut longest_forward_jump: Option<Jump> = None;
+    let mut longest_backward_jump: Option<Jump> = None;
+    let outcome = run_jumps_observed(instructions, bump_jump_with, max_steps, |jump| {
+        visits[jump.from] += 1;
+        if jump.offset > longest_forward_jump.map(|j| j.offset).unwrap_or(0) {
+            longest_forward_jump = Some(jump);
+        }
+        if jump.offset < longest_backward_jump.map(|j| j.offset).unwrap_or(0) {
+            longest_backward_jump = Some(jump);
+        }
+    })?;
+    Ok(JumpTrace {
+        outcome,
+        initial_offsets: instructions.clone(),
+        visits,
+        longest_forward_jump,
+        longest_backward_jump,
+    })
+}
+
+impl JumpTrace {
+    pub fn final_offsets(&self) -> &Vec<i64> {
+        match self.outcome {
+            JumpOutcome::Escaped(ref state)
+            | JumpOutcome::BudgetExhausted(ref state)
+            | JumpOutcome::Looping { ref state, .. } => &state.offsets,
         }
-        Ok(steps_taken)
+    }
+
+    /// Writes one CSV row per index, with its initial and final offsets and how
+    /// often it was jumped from.
+    pub fn write_csv<W>(&self, mut w: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writeln!(w, "index,initial_offset,final_offset,visits")?;
+        for (idx, ((initial, last), visits)) in self.initial_offsets
+            .iter()
+            .zip(self.final_offsets().iter())
+            .zip(self.visits.iter())
+            .enumerate()
+        {
+            writeln!(w, "{},{},{},{}", idx, initial, last, visits)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut buf = Vec::new();
+        // Writing to a Vec can't fail, and we only ever write UTF-8
+        self.write_csv(&mut buf)
+            .expect("Failed to write CSV to memory");
+        String::from_utf8(buf).expect("CSV was not UTF-8")
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use common::SplitMix64;
     use day_5::*;
 
     #[test]
@@ -67,4 +431,223 @@ mod tests {
         );
     }
 
+    #[test]
+    fn run_jumps_escaped_test() {
+        let outcome = run_jumps(
+            &vec![0, 3, 0, 1, -3],
+            |j| if j >= 3 { j - 1 } else { j + 1 },
+            100,
+        );
+        assert_eq!(
+            outcome,
+            Ok(JumpOutcome::Escaped(JumpState {
+                steps: 10,
+                idx: 5,
+                offsets: vec![2, 3, 2, 3, -1],
+            }))
+        );
+        let backwards = run_jumps(&vec![-1], |j| j + 1, 100);
+        assert_eq!(
+            backwards,
+            Ok(JumpOutcome::Escaped(JumpState {
+                steps: 1,
+                idx: -1,
+                offsets: vec![0],
+            }))
+        );
+        let overflowing = run_jumps(&vec![1, i64::max_value()], |j| j, 100);
+        assert!(match overflowing {
+            Ok(JumpOutcome::Escaped(JumpState { steps: 2, .. })) => true,
+            _ => false,
+        });
+        assert!(run_jumps(&vec![], |j| j + 1, 100).is_err());
+    }
+
+    #[test]
+    fn run_jumps_budget_test() {
+        let outcome = run_jumps(&vec![0, 3, 0, 1, -3], |j| j + 1, 3);
+        assert_eq!(
+            outcome,
+            Ok(JumpOutcome::BudgetExhausted(JumpState {
+                steps: 3,
+                idx: 4,
+                offsets: vec![2, 4, 0, 1, -3],
+            }))
+        );
+        // Too long for loop detection, so this only stops thanks to the budget
+        let mut long_loop = vec![0; LOOP_DETECTION_MAX_LEN + 1];
+        long_loop[0] = 1;
+        long_loop[1] = -1;
+        let outcome = run_jumps(&long_loop, |j| j, 1000);
+        assert!(match outcome {
+            Ok(JumpOutcome::BudgetExhausted(JumpState { steps: 1000, .. })) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn escape_strange_jumps_test() {
+        assert_eq!(
+            escape_strange_jumps(&vec![0, 3, 0, 1, -3]),
+            Ok(JumpState {
+                steps: 10,
+                idx: 5,
+                offsets: vec![2, 3, 2, 3, -1],
+            })
+        );
+        assert!(escape_strange_jumps(&vec![]).is_err());
+    }
+
+    fn assert_same_as_generic(instructions: &Vec<i64>) {
+        let generic = run_jumps(instructions, strange_bump, u64::max_value());
+        let fast = escape_strange_jumps(instructions);
+        assert_eq!(
+            fast.map(JumpOutcome::Escaped),
+            generic,
+            "instructions: {:?}",
+            instructions
+        );
+    }
+
+    #[test]
+    fn escape_strange_jumps_differential_test() {
+        let mut rng = SplitMix64(5);
+        for _ in 0..2000 {
+            let len = 1 + rng.below(100);
+            // Mostly short jumps, with some long backwards ones to make the
+            // pointer go back through settled chunks
+            let instructions: Vec<i64> = (0..len)
+                .map(|_| match rng.below(10) {
+                    0 => -(rng.below(len + 2) as i64),
+                    1 => rng.below(len + 2) as i64,
+                    _ => rng.below(8) as i64 - 4,
+                })
+                .collect();
+            assert_same_as_generic(&instructions);
+        }
+        // Too big to store as i16s
+        assert_same_as_generic(&vec![0, 40_000, -1, -70_000]);
+    }
+
+    #[test]
+    fn escape_strange_jumps_real_test() {
+        let instructions = instructions_str_to_vec(DAY_5_INPUT);
+        assert_same_as_generic(&instructions);
+        assert_eq!(steps_to_escape_next(DAY_5_INPUT), Ok(27502966));
+    }
+
+    #[test]
+    fn trace_jumps_test() {
+        let trace = trace_jumps(
+            &vec![0, 3, 0, 1, -3],
+            |j| if j >= 3 { j - 1 } else { j + 1 },
+            100,
+        ).unwrap();
+        assert!(match trace.outcome {
+            JumpOutcome::Escaped(JumpState { steps: 10, .. }) => true,
+            _ => false,
+        });
+        assert_eq!(trace.final_offsets(), &vec![2, 3, 2, 3, -1]);
+        assert_eq!(trace.visits, vec![2, 2, 2, 2, 2]);
+        assert_eq!(trace.visits.iter().sum::<u64>(), 10);
+        assert_eq!(
+            trace.longest_forward_jump,
+            Some(Jump {
+                step: 3,
+                from: 1,
+                offset: 3,
+            })
+        );
+        assert_eq!(
+            trace.longest_backward_jump,
+            Some(Jump {
+                step: 4,
+                from: 4,
+                offset: -3,
+            })
+        );
+        assert_eq!(
+            trace.to_csv(),
+            "index,initial_offset,final_offset,visits
+0,0,2,2
+1,3,3,2
+2,0,2,2
+3,1,3,2
+4,-3,-1,2
+"
+        );
+    }
+
+    #[test]
+    fn trace_jumps_no_jumps_test() {
+        let trace = trace_jumps(&vec![0], |j| j, 5).unwrap();
+        assert_eq!(trace.visits, vec![1]);
+        assert_eq!(trace.longest_forward_jump, None);
+        assert_eq!(trace.longest_backward_jump, None);
+    }
+
+    #[test]
+    fn run_jumps_looping_test() {
+        let stuck = run_jumps(&vec![0], |j| j, u64::max_value());
+        assert_eq!(
+            stuck,
+            Ok(JumpOutcome::Looping {
+                state: JumpState {
+                    steps: 1,
+                    idx: 0,
+                    offsets: vec![0],
+                },
+                loop_start: 0,
+                loop_length: 1,
+            })
+        );
+        // Takes a couple of steps to get stuck on the last offset
+        let flipping = run_jumps(&vec![1, 1, 0], |j| -j, 1000);
+        assert_eq!(
+            flipping,
+            Ok(JumpOutcome::Looping {
+                state: JumpState {
+                    steps: 4,
+                    idx: 2,
+                    offsets: vec![-1, -1, 0],
+                },
+                loop_start: 2,
+                loop_length: 1,
+            })
+        );
+        assert_eq!(
+            steps_to_escape_vec_inner(&vec![1, -1], |j| j),
+            Err("Instructions loop forever")
+        );
+    }
+
+    #[test]
+    fn run_jumps_loop_at_budget_test() {
+        // Comes back around to index 2 every two steps, after a couple of steps,
+        // which gets noticed on getting back to the checkpoint left at index 3
+        let instructions = vec![1, 1, 1, -1];
+        let looping = JumpOutcome::Looping {
+            state: JumpState {
+                steps: 5,
+                idx: 3,
+                offsets: vec![1, 1, 1, -1],
+            },
+            loop_start: 2,
+            loop_length: 2,
+        };
+        assert_eq!(run_jumps(&instructions, |j| j, 5), Ok(looping.clone()));
+        assert_eq!(run_jumps(&instructions, |j| j, 1000), Ok(looping));
+        assert_eq!(
+            run_jumps(&instructions, |j| j, 4),
+            Ok(JumpOutcome::BudgetExhausted(JumpState {
+                steps: 4,
+                idx: 2,
+                offsets: vec![1, 1, 1, -1],
+            }))
+        );
+        // Only the jumps up to where the loop got noticed are traced
+        let trace = trace_jumps(&instructions, |j| j, 1000).unwrap();
+        assert_eq!(trace.visits, vec![1, 1, 2, 1]);
+    }
+
 }
@@ -1,5 +1,6 @@
-use std::u64;
-use std::collections::HashSet;
+use common::{find_cycle, Cycle};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
 
 const DAY_6_INPUT: &str = include_str!("../data/day_6_input");
 
@@ -7,18 +8,14 @@ pub fn run() -> Result<(), String> {
     println!("*** Day 6: Memory Reallocation ***");
     println!("Input: {}", DAY_6_INPUT);
     let mut redistributer = RedistributionCycles::new(DAY_6_INPUT);
-    println!("Solution 1: {:?}\n", redistributer.redist()?);
+    println!("Solution 1: {:?}\n", redistributer.redist());
     println!("Solution 2: {:?}\n", redistributer.loop_size()?);
     Ok(())
 }
 
 struct RedistributionCycles {
-    seen_configs: Vec<Vec<u64>>,
-    // This is just an optimisation so we can look for seen configs faster than
-    // looping through a Vector
-    seen_configs_hash: HashSet<Vec<u64>>,
-    current: Vec<u64>,
-    cycles: u64,
+    init: Vec<u64>,
+    cycle: Option<Cycle>,
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -32,65 +29,182 @@ impl RedistributionCycles {
         let init = s.split("\t")
             .filter_map(|s| s.trim().parse().ok())
             .collect();
-        RedistributionCycles {
-            seen_configs: Vec::new(),
-            seen_configs_hash: HashSet::new(),
-            current: init,
-            cycles: 0,
-        }
+        RedistributionCycles { init, cycle: None }
     }
 
     fn loop_size(&self) -> Result<LoopCycle, &str> {
-        if self.cycles == 0 {
-            Err("redist() not yet run.")
-        } else {
-            let pos = self.seen_configs.iter().position(|v| *v == self.current);
-            match pos {
-                Some(idx) => Ok(LoopCycle(self.cycles - idx as u64)),
-                None => Err("No loop found"),
-            }
+        match self.cycle {
+            Some(cycle) => Ok(LoopCycle(cycle.cycle_len as u64)),
+            None => Err("redist() not yet run."),
         }
     }
 
-    fn redist(&mut self) -> Result<RepeatsAfter, String> {
-        while !self.seen_configs_hash.contains(&self.current) && self.cycles <= u64::max_value() {
-            self.redist_once()
+    // The total number of blocks never changes, so there are only finitely
+    // many configurations and we're bound to see one again.
+    fn redist(&mut self) -> RepeatsAfter {
+        let cycle = redistribution_cycle(&self.init, &PUZZLE_RULES);
+        self.cycle = Some(cycle);
+        RepeatsAfter(cycle.repeats_after() as u64)
+    }
+}
+
+/// Which bank gets emptied when several of them hold the most blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TieBreak {
+    LowestIndex,
+    HighestIndex,
+}
+
+/// Which way round the banks the blocks are handed out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// How many banks, counting on from the emptied one, share its blocks.
+///
+/// `AllBanks` includes the emptied bank itself, last. Spreading over 0 banks
+/// means nothing ever moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Spread {
+    AllBanks,
+    Banks(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RedistributionRules {
+    pub tie_break: TieBreak,
+    pub direction: Direction,
+    pub spread: Spread,
+}
+
+pub const PUZZLE_RULES: RedistributionRules = RedistributionRules {
+    tie_break: TieBreak::LowestIndex,
+    direction: Direction::Forward,
+    spread: Spread::AllBanks,
+};
+
+/// Empties the fullest bank and hands its blocks out one at a time to the
+/// banks after it, going round the spread as many times as it takes.
+pub fn redistribute(banks: &[u64], rules: &RedistributionRules) -> Vec<u64> {
+    let mut next = banks.to_vec();
+    let len = next.len();
+    let width = match rules.spread {
+        Spread::AllBanks => len,
+        Spread::Banks(width) => width,
+    };
+    if len == 0 || width == 0 {
+        return next;
+    }
+    let idx = find_redist_target_idx(banks, rules.tie_break);
+    let redis_load = next[idx];
+    next[idx] = 0;
+    let each = redis_load / width as u64;
+    let extra = redis_load % width as u64;
+    // Only the first `extra` banks get anything if there's less than a block each
+    let receiving = if each == 0 { extra as usize } else { width };
+    for i in 0..receiving {
+        let distance = (i + 1) % len;
+        let target = match rules.direction {
+            Direction::Forward => (idx + distance) % len,
+            Direction::Backward => (idx + len - distance) % len,
+        };
+        next[target] += each + if (i as u64) < extra { 1 } else { 0 };
+    }
+    next
+}
+
+/// Redistributes from `banks` until a configuration comes round again.
+///
+/// The total number of blocks never changes, so there are only finitely many
+/// configurations and one is bound to.
+pub fn redistribution_cycle(banks: &[u64], rules: &RedistributionRules) -> Cycle {
+    find_cycle(banks.to_vec(), |current| redistribute(current, rules))
+}
+
+/// How many configurations had each transient length and each cycle length.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CycleCensus {
+    pub configurations: u64,
+    pub transient_lens: BTreeMap<usize, u64>,
+    pub cycle_lens: BTreeMap<usize, u64>,
+}
+
+impl CycleCensus {
+    fn add(mut self, cycle: Cycle) -> CycleCensus {
+        self.configurations += 1;
+        *self.transient_lens.entry(cycle.transient_len).or_insert(0) += 1;
+        *self.cycle_lens.entry(cycle.cycle_len).or_insert(0) += 1;
+        self
+    }
+
+    fn merge(mut self, other: CycleCensus) -> CycleCensus {
+        self.configurations += other.configurations;
+        for (len, count) in other.transient_lens {
+            *self.transient_lens.entry(len).or_insert(0) += count;
         }
-        if !self.seen_configs_hash.contains(&self.current) {
-            Err(format!(
-                "Cycled {} times and couldn't find a repeat",
-                self.cycles
-            ))
-        } else {
-            Ok(RepeatsAfter(self.cycles))
+        for (len, count) in other.cycle_lens {
+            *self.cycle_lens.entry(len).or_insert(0) += count;
         }
+        self
     }
+}
 
-    fn redist_once(&mut self) -> () {
-        // Archive current form
-        self.seen_configs.push(self.current.clone());
-        self.seen_configs_hash.insert(self.current.clone());
-
-        let mut idx = find_redist_target_idx(&self.current);
-        let mut redis_load = self.current[idx];
-        self.current[idx] = 0;
-        while redis_load > 0 {
-            if idx + 1 >= self.current.len() {
-                idx = 0;
-            } else {
-                idx += 1;
-            }
-            self.current[idx] += 1;
-            redis_load -= 1;
-        }
-        self.cycles += 1;
+/// Finds the cycle for every configuration of 1 to `max_banks` banks holding
+/// at most `max_total` blocks between them, in parallel.
+///
+/// Configurations are split up by what's in the first bank, and each of those
+/// gets enumerated as it's counted, so none of them are held on to.
+pub fn cycle_census(max_banks: usize, max_total: u64, rules: &RedistributionRules) -> CycleCensus {
+    (1..max_banks + 1)
+        .into_par_iter()
+        .flat_map(|bank_count| {
+            (0..max_total + 1).into_par_iter().map(move |first| {
+                let mut banks = Vec::with_capacity(bank_count);
+                banks.push(first);
+                census_configurations(
+                    CycleCensus::default(),
+                    &mut banks,
+                    bank_count,
+                    max_total - first,
+                    rules,
+                )
+            })
+        })
+        .reduce(CycleCensus::default, CycleCensus::merge)
+}
+
+/// Adds every configuration of `bank_count` banks starting with `banks` and
+/// holding at most `max_total` more blocks to `census`.
+fn census_configurations(
+    census: CycleCensus,
+    banks: &mut Vec<u64>,
+    bank_count: usize,
+    max_total: u64,
+    rules: &RedistributionRules,
+) -> CycleCensus {
+    if banks.len() == bank_count {
+        census.add(redistribution_cycle(banks, rules))
+    } else {
+        (0..max_total + 1).fold(census, |census, blocks| {
+            banks.push(blocks);
+            let census =
+                census_configurations(census, banks, bank_count, max_total - blocks, rules);
+            banks.pop();
+            census
+        })
     }
 }
 
-fn find_redist_target_idx(v: &Vec<u64>) -> usize {
+fn find_redist_target_idx(v: &[u64], tie_break: TieBreak) -> usize {
     v.iter().enumerate().fold(0, |acc, (idx, next)| {
         let last_biggest = v[acc];
-        if *next > last_biggest {
+        let replaces = match tie_break {
+            TieBreak::LowestIndex => *next > last_biggest,
+            TieBreak::HighestIndex => *next >= last_biggest,
+        };
+        if replaces {
             idx
         } else {
             acc
@@ -104,13 +218,86 @@ mod tests {
 
     #[test]
     fn find_redist_target_idx_test() {
-        assert_eq!(find_redist_target_idx(&vec![0, 2, 7, 0]), 2);
-        assert_eq!(find_redist_target_idx(&vec![3, 1, 2, 3]), 0);
+        assert_eq!(find_redist_target_idx(&vec![0, 2, 7, 0], TieBreak::LowestIndex), 2);
+        assert_eq!(find_redist_target_idx(&vec![3, 1, 2, 3], TieBreak::LowestIndex), 0);
+        assert_eq!(find_redist_target_idx(&vec![3, 1, 2, 3], TieBreak::HighestIndex), 3);
+    }
+
+    #[test]
+    fn redistribute_test() {
+        let steps = vec![
+            vec![0, 2, 7, 0],
+            vec![2, 4, 1, 2],
+            vec![3, 1, 2, 3],
+            vec![0, 2, 3, 4],
+            vec![1, 3, 4, 1],
+            vec![2, 4, 1, 2],
+        ];
+        for pair in steps.windows(2) {
+            assert_eq!(redistribute(&pair[0], &PUZZLE_RULES), pair[1]);
+        }
+        let rules = RedistributionRules {
+            tie_break: TieBreak::HighestIndex,
+            direction: Direction::Backward,
+            spread: Spread::Banks(2),
+        };
+        assert_eq!(redistribute(&vec![3, 1, 2, 3], &rules), vec![3, 2, 4, 0]);
+        assert_eq!(redistribute(&vec![7, 0, 0, 0], &rules), vec![0, 0, 3, 4]);
+        // Wider than the banks, so it goes round more than once
+        let wide = RedistributionRules {
+            spread: Spread::Banks(6),
+            ..PUZZLE_RULES
+        };
+        assert_eq!(redistribute(&vec![0, 8, 0], &wide), vec![3, 2, 3]);
+        let none = RedistributionRules {
+            spread: Spread::Banks(0),
+            ..PUZZLE_RULES
+        };
+        assert_eq!(redistribute(&vec![0, 8, 0], &none), vec![0, 8, 0]);
+        assert_eq!(redistribute(&vec![], &PUZZLE_RULES), vec![]);
+    }
+
+    #[test]
+    fn cycle_census_test() {
+        let census = cycle_census(2, 2, &PUZZLE_RULES);
+        assert_eq!(
+            census,
+            CycleCensus {
+                configurations: 9,
+                transient_lens: btreemap!{0 => 8, 1 => 1},
+                cycle_lens: btreemap!{1 => 4, 2 => 5},
+            }
+        );
+    }
+
+    #[test]
+    fn cycle_census_matches_sequential_test() {
+        let rules = RedistributionRules {
+            tie_break: TieBreak::HighestIndex,
+            direction: Direction::Backward,
+            spread: Spread::Banks(2),
+        };
+        let census = cycle_census(4, 5, &rules);
+        let expected = (1..5).fold(CycleCensus::default(), |census, bank_count| {
+            census_configurations(census, &mut Vec::new(), bank_count, 5, &rules)
+        });
+        // (5 + n) choose n configurations of n banks
+        assert_eq!(expected.configurations, 6 + 21 + 56 + 126);
+        assert_eq!(census, expected);
     }
 
     #[test]
     fn find_repeat_test() {
         let mut runner = RedistributionCycles::new("0\t2\t7\t0");
-        assert_eq!(runner.redist(), Ok(RepeatsAfter(5)));
+        assert_eq!(runner.loop_size(), Err("redist() not yet run."));
+        assert_eq!(runner.redist(), RepeatsAfter(5));
+        assert_eq!(runner.loop_size(), Ok(LoopCycle(4)));
+    }
+
+    #[test]
+    fn real_test() {
+        let mut runner = RedistributionCycles::new(DAY_6_INPUT);
+        assert_eq!(runner.redist(), RepeatsAfter(11137));
+        assert_eq!(runner.loop_size(), Ok(LoopCycle(1037)));
     }
 }
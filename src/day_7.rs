@@ -1,24 +1,231 @@
 use regex::*;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ptr;
+use std::str::FromStr;
 
 const NAME_GROUP: &str = "name";
 const WEIGHT_GROUP: &str = "weight";
 const HOLDING_UP_GROUP: &str = "holding_up";
 const DAY_7_INPUT: &str = include_str!("../data/day_7_input");
 
-pub fn run() -> Result<(), &'static str> {
+pub fn run() -> Result<(), String> {
     println!("*** Day 7: Recursive Circus ***");
     println!("Input: {}", DAY_7_INPUT);
-    let tree = Node::from_str(DAY_7_INPUT)?;
-    println!("Solution 1: {:?}\n", tree.name);
-    let with_kid_weights = NodeWithChildrenWeight::build(&tree);
-    println!(
-        "Solution 2: {:?}\n",
-        with_kid_weights.smallest_rebalanced_children_weight()
-    );
+    let tower = Tower::from_str(DAY_7_INPUT).map_err(|e| e.to_string())?;
+    println!("Solution 1: {}\n", tower.name(tower.root()));
+    println!("Solution 2: {}\n", tower.rebalance_report());
     Ok(())
 }
 
+/// Renders the tower described by `s` as a Graphviz DOT digraph, with
+/// unbalanced programs in red.
+pub fn tower_to_dot(s: &str) -> Result<String, InvalidTower> {
+    Ok(Tower::from_str(s)?.to_dot())
+}
+
+/// Renders the tower described by `s` as an indented text tree, like `tree`
+/// does for directories.
+pub fn tower_to_text_tree(s: &str) -> Result<String, InvalidTower> {
+    Ok(Tower::from_str(s)?.to_text_tree())
+}
+
+/// Works out which program's weight needs changing to balance the tower
+/// described by `s`.
+pub fn rebalance_report(s: &str) -> Result<RebalanceReport, InvalidTower> {
+    Ok(Tower::from_str(s)?.rebalance_report())
+}
+
+/// Checks that `s` describes a single tower, reporting everything that stops
+/// it from doing so.
+pub fn validate_tower(s: &str) -> Result<(), InvalidTower> {
+    let (entries, mut problems) = NodeEntry::parse_with_problems(s);
+    problems.extend(validate_entries(&entries));
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(InvalidTower(problems))
+    }
+}
+
+/// Something wrong with a tower's description. Lines count from 1.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TowerProblem {
+    Unparseable { line: usize },
+    DuplicateName { name: String, lines: Vec<usize> },
+    MissingChild { parent: String, line: usize, child: String },
+    /// `lines` are those of the programs holding it up.
+    MultipleParents { name: String, lines: Vec<usize> },
+    NoRoot,
+    MultipleRoots { roots: Vec<(String, usize)> },
+    /// Programs that end up holding themselves up, in order.
+    Cycle { programs: Vec<(String, usize)> },
+    /// A program that isn't part of a cycle, but is only held up by one, so
+    /// that neither it nor what it holds up can be reached from the bottom.
+    OrphanedSubtree { name: String, line: usize },
+}
+
+impl fmt::Display for TowerProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join_lines = |lines: &Vec<usize>| {
+            lines
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let join_programs = |programs: &Vec<(String, usize)>, separator: &str| {
+            programs
+                .iter()
+                .map(|&(ref name, line)| format!("{} (line {})", name, line))
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        match self {
+            &TowerProblem::Unparseable { line } => write!(f, "line {}: couldn't parse", line),
+            &TowerProblem::DuplicateName { ref name, ref lines } => write!(
+                f,
+                "{} is described more than once, on lines {}",
+                name,
+                join_lines(lines)
+            ),
+            &TowerProblem::MissingChild {
+                ref parent,
+                line,
+                ref child,
+            } => write!(
+                f,
+                "line {}: {} holds up {}, which doesn't exist",
+                line, parent, child
+            ),
+            &TowerProblem::MultipleParents { ref name, ref lines } => write!(
+                f,
+                "{} is held up by more than one program, on lines {}",
+                name,
+                join_lines(lines)
+            ),
+            &TowerProblem::NoRoot => write!(f, "no program is at the bottom of the tower"),
+            &TowerProblem::MultipleRoots { ref roots } => write!(
+                f,
+                "more than one program is at the bottom: {}",
+                join_programs(roots, ", ")
+            ),
+            &TowerProblem::Cycle { ref programs } => write!(
+                f,
+                "programs hold each other up: {} -> {}",
+                join_programs(programs, " -> "),
+                programs[0].0
+            ),
+            &TowerProblem::OrphanedSubtree { ref name, line } => write!(
+                f,
+                "line {}: {} is only held up by programs that hold each other up",
+                line, name
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InvalidTower(pub Vec<TowerProblem>);
+
+impl fmt::Display for InvalidTower {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problems: Vec<_> = self.0.iter().map(|p| p.to_string()).collect();
+        write!(f, "Invalid tower:\n{}", problems.join("\n"))
+    }
+}
+
+/// A program whose discs don't all weigh the same.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Imbalance {
+    /// Names from the bottom program up to this one.
+    pub path: Vec<String>,
+    /// Total weight of each disc it holds up, by the name of the program at its
+    /// base.
+    pub sibling_totals: Vec<(String, usize)>,
+}
+
+/// A single weight change that balances the whole tower.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Rebalance {
+    /// Names from the bottom program up to the one with the wrong weight.
+    pub path: Vec<String>,
+    pub current_weight: usize,
+    pub corrected_weight: usize,
+    /// Total weight of the wrong program's disc and those of its siblings.
+    pub sibling_totals: Vec<(String, usize)>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RebalanceReport {
+    Balanced,
+    Rebalance(Rebalance),
+    /// A program holds up exactly two discs that weigh different amounts, and
+    /// changing either one would balance the tower.
+    AmbiguousPair(Imbalance),
+    /// No single weight change balances the tower.
+    MultipleImbalances(Vec<Imbalance>),
+}
+
+impl fmt::Display for RebalanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RebalanceReport::Balanced => write!(f, "The tower is balanced"),
+            &RebalanceReport::Rebalance(ref r) => write!(
+                f,
+                "{} weighs {} but should weigh {} (sibling totals: {})",
+                r.path.join(" -> "),
+                r.current_weight,
+                r.corrected_weight,
+                format_totals(&r.sibling_totals)
+            ),
+            &RebalanceReport::AmbiguousPair(ref i) => write!(
+                f,
+                "{} holds up two discs of different weights, either of which could be wrong \
+                 (totals: {})",
+                i.path.join(" -> "),
+                format_totals(&i.sibling_totals)
+            ),
+            &RebalanceReport::MultipleImbalances(ref imbalances) => {
+                write!(
+                    f,
+                    "No single weight change balances the tower. Unbalanced programs:"
+                )?;
+                for i in imbalances.iter() {
+                    write!(
+                        f,
+                        "\n  {} (totals: {})",
+                        i.path.join(" -> "),
+                        format_totals(&i.sibling_totals)
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn format_totals(totals: &Vec<(String, usize)>) -> String {
+    totals
+        .iter()
+        .map(|&(ref name, total)| format!("{} {}", name, total))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The total all of `totals` share, if there are any and they do.
+fn uniform_total<I>(mut totals: I) -> Option<usize>
+where
+    I: Iterator<Item = usize>,
+{
+    let first = totals.next()?;
+    if totals.all(|t| t == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
 lazy_static! {
     static ref ENTRIES_MATCHER: Regex = {
         Regex::new(
@@ -40,150 +247,704 @@ struct NodeEntry {
     name: Name,
     weight: usize,
     holding_up: Vec<Name>,
+    line: usize,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug)]
-struct Node {
-    name: Name,
+/// Index of a program in a `Tower`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+pub struct ProgramId(u32);
+
+// Stands in for a missing parent, child or sibling.
+const NO_PROGRAM: u32 = u32::MAX;
+
+// Kept small, since there's one per program: names all live in one String
+// (each running up to where the next one starts), and the programs each holds
+// up are a linked list through `next_sibling`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct Program {
+    name_start: u32,
+    parent: u32,
+    first_child: u32,
+    last_child: u32,
+    next_sibling: u32,
+    // How many unbalanced programs there are from this one up
+    unbalanced: u32,
+    // How many different total weights the programs this holds up have, so
+    // it's balanced when there's at most one
+    distinct_child_totals: u32,
     weight: usize,
-    children: Vec<Node>,
+    // Weight of this program plus everything it holds up
+    total_weight: usize,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
-struct NodeWithChildrenWeight<'a> {
-    node: &'a Node,
-    children_with_weights: Vec<NodeWithChildrenWeight<'a>>,
-    children_weight: usize,
+/// A tower of programs, stored in an arena so that it can be built and walked
+/// without recursion, however tall it is.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Tower {
+    names: String,
+    programs: Vec<Program>,
+    root: ProgramId,
+    // How many of a program's children have each total weight, by program
+    child_totals: HashMap<(u32, usize), u32>,
+}
+
+/// The programs held up by one program, in the order they were described.
+pub struct Children<'a> {
+    tower: &'a Tower,
+    next: u32,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = ProgramId;
+
+    fn next(&mut self) -> Option<ProgramId> {
+        if self.next == NO_PROGRAM {
+            None
+        } else {
+            let id = ProgramId(self.next);
+            self.next = self.tower.program(id).next_sibling;
+            Some(id)
+        }
+    }
 }
 
-impl<'a> NodeWithChildrenWeight<'a> {
-    fn build(node: &'a Node) -> NodeWithChildrenWeight<'a> {
-        let children_with_weights: Vec<_> = node.children
+impl FromStr for Tower {
+    type Err = InvalidTower;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (entries, problems) = NodeEntry::parse_with_problems(s);
+        if problems.is_empty() {
+            Tower::from_entries(&entries)
+        } else {
+            Err(InvalidTower(problems))
+        }
+    }
+}
+
+impl Tower {
+    /// A tower of a single program, to build on with `add_program` and `attach`.
+    pub fn new(root_name: &str, weight: usize) -> Tower {
+        let mut tower = Tower {
+            names: String::new(),
+            programs: Vec::new(),
+            root: ProgramId(0),
+            child_totals: HashMap::new(),
+        };
+        tower.add_program(root_name, weight);
+        tower
+    }
+
+    fn from_entries(entries: &Vec<NodeEntry>) -> Result<Tower, InvalidTower> {
+        let problems = validate_entries(entries);
+        if !problems.is_empty() {
+            return Err(InvalidTower(problems));
+        }
+        let root_entry =
+            find_root(entries).ok_or_else(|| InvalidTower(vec![TowerProblem::NoRoot]))?;
+        // Valid entries have unique names, so each becomes the program with
+        // the same index.
+        let ids: HashMap<&Name, u32> = entries
             .iter()
-            .map(NodeWithChildrenWeight::build)
+            .enumerate()
+            .map(|(idx, entry)| (&entry.name, idx as u32))
             .collect();
-        let children_weight = children_with_weights.iter().fold(
-            0,
-            |acc, child_with_children_weights| {
-                acc + child_with_children_weights.children_weight
-                    + child_with_children_weights.node.weight
-            },
-        );
-        NodeWithChildrenWeight {
-            node: node,
-            children_with_weights: children_with_weights,
-            children_weight: children_weight,
-        }
-    }
-
-    fn total_weight(&self) -> usize {
-        self.children_weight + self.node.weight
-    }
-
-    fn smallest_rebalanced_children_weight(&self) -> Result<isize, &'static str> {
-        let children_smallest = self.children_with_weights.iter().fold(
-            Err("No need for rebalancing"),
-            |acc, next| {
-                let next_smallest_r = next.smallest_rebalanced_children_weight();
-                match (acc, next_smallest_r) {
-                    (Err(_), r) => r,
-                    (ok @ Ok(_), Err(_)) => ok,
-                    (Ok(last_smallest), Ok(next_smallest)) => if next_smallest < last_smallest {
-                        Ok(next_smallest)
-                    } else {
-                        acc
-                    },
+        let mut names = String::with_capacity(entries.iter().map(|e| e.name.0.len()).sum());
+        let mut programs = Vec::with_capacity(entries.len());
+        for entry in entries.iter() {
+            programs.push(Program {
+                name_start: names.len() as u32,
+                parent: NO_PROGRAM,
+                first_child: NO_PROGRAM,
+                last_child: NO_PROGRAM,
+                next_sibling: NO_PROGRAM,
+                unbalanced: 0,
+                distinct_child_totals: 0,
+                weight: entry.weight,
+                total_weight: entry.weight,
+            });
+            names.push_str(&entry.name.0);
+        }
+        for (idx, entry) in entries.iter().enumerate() {
+            // Backwards, so that the list ends up in the original order
+            for child_name in entry.holding_up.iter().rev() {
+                let child = ids[child_name] as usize;
+                programs[child].parent = idx as u32;
+                programs[child].next_sibling = programs[idx].first_child;
+                programs[idx].first_child = child as u32;
+                if programs[idx].last_child == NO_PROGRAM {
+                    programs[idx].last_child = child as u32;
                 }
-            },
-        );
-        children_smallest.or(self.rebalanced_children_weight())
+            }
+        }
+        let mut tower = Tower {
+            names,
+            programs,
+            root: ProgramId(ids[&root_entry.name]),
+            // One total each for balanced programs, which is nearly all of them
+            child_totals: HashMap::with_capacity(
+                entries.iter().filter(|e| !e.holding_up.is_empty()).count(),
+            ),
+        };
+        // Everything comes after what holds it up in the pre-order, so going
+        // through it backwards adds up the totals from the top down.
+        let order = tower.pre_order();
+        for id in order.into_iter().rev() {
+            if !tower.is_balanced(id) {
+                tower.programs[id.0 as usize].unbalanced += 1;
+            }
+            let (parent, total_weight, unbalanced) = {
+                let program = tower.program(id);
+                (program.parent, program.total_weight, program.unbalanced)
+            };
+            if parent != NO_PROGRAM {
+                tower.programs[parent as usize].total_weight += total_weight;
+                tower.programs[parent as usize].unbalanced += unbalanced;
+                tower.add_child_total(parent, total_weight);
+            }
+        }
+        Ok(tower)
+    }
+
+    fn program(&self, id: ProgramId) -> &Program {
+        &self.programs[id.0 as usize]
+    }
+
+    /// The program at the bottom.
+    pub fn root(&self) -> ProgramId {
+        self.root
+    }
+
+    pub fn name(&self, id: ProgramId) -> &str {
+        let start = self.program(id).name_start as usize;
+        let end = self.programs
+            .get(id.0 as usize + 1)
+            .map(|next| next.name_start as usize)
+            .unwrap_or(self.names.len());
+        &self.names[start..end]
+    }
+
+    /// Looks a program up by name, going through all of them.
+    pub fn find(&self, name: &str) -> Option<ProgramId> {
+        (0..self.programs.len() as u32)
+            .map(ProgramId)
+            .find(|id| self.name(*id) == name)
+    }
+
+    pub fn weight(&self, id: ProgramId) -> usize {
+        self.program(id).weight
     }
 
-    fn rebalanced_children_weight(&self) -> Result<isize, &'static str> {
-        let kid_weights = self.children_with_weights.as_slice();
-        if kid_weights.len() <= 1 {
-            Err("There are 1 or less children weights. No need for rebalancing")
+    /// Weight of the program plus everything it holds up.
+    pub fn total_weight(&self, id: ProgramId) -> usize {
+        self.program(id).total_weight
+    }
+
+    pub fn parent(&self, id: ProgramId) -> Option<ProgramId> {
+        match self.program(id).parent {
+            NO_PROGRAM => None,
+            parent => Some(ProgramId(parent)),
+        }
+    }
+
+    pub fn children<'a>(&'a self, id: ProgramId) -> Children<'a> {
+        Children {
+            tower: self,
+            next: self.program(id).first_child,
+        }
+    }
+
+    /// Whether all the discs this program holds up weigh the same.
+    pub fn is_balanced(&self, id: ProgramId) -> bool {
+        self.program(id).distinct_child_totals <= 1
+    }
+
+    fn add_child_total(&mut self, parent: u32, total_weight: usize) -> () {
+        let count = self.child_totals.entry((parent, total_weight)).or_insert(0);
+        if *count == 0 {
+            self.programs[parent as usize].distinct_child_totals += 1;
+        }
+        *count += 1;
+    }
+
+    fn remove_child_total(&mut self, parent: u32, total_weight: usize) -> () {
+        let now_none = match self.child_totals.get_mut(&(parent, total_weight)) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if now_none {
+            self.child_totals.remove(&(parent, total_weight));
+            self.programs[parent as usize].distinct_child_totals -= 1;
+        }
+    }
+
+    /// Whether this program and everything it holds up are balanced.
+    pub fn is_subtree_balanced(&self, id: ProgramId) -> bool {
+        self.program(id).unbalanced == 0
+    }
+
+    /// Changes a program's weight, updating everything below it.
+    pub fn set_weight(&mut self, id: ProgramId, weight: usize) -> () {
+        let delta = weight as isize - self.weight(id) as isize;
+        self.programs[id.0 as usize].weight = weight;
+        self.update_totals(id, delta, 0);
+    }
+
+    /// Adds a program that isn't held up by anything yet, for `attach`.
+    ///
+    /// Names aren't checked for clashes; they're only used for output and
+    /// `find`.
+    pub fn add_program(&mut self, name: &str, weight: usize) -> ProgramId {
+        let id = ProgramId(self.programs.len() as u32);
+        self.programs.push(Program {
+            name_start: self.names.len() as u32,
+            parent: NO_PROGRAM,
+            first_child: NO_PROGRAM,
+            last_child: NO_PROGRAM,
+            next_sibling: NO_PROGRAM,
+            unbalanced: 0,
+            distinct_child_totals: 0,
+            weight,
+            total_weight: weight,
+        });
+        self.names.push_str(name);
+        id
+    }
+
+    /// Takes `id`, and everything it holds up, off the program holding it up.
+    ///
+    /// The detached programs stay around, to be attached again.
+    pub fn detach(&mut self, id: ProgramId) -> Result<(), &'static str> {
+        let parent = self.parent(id).ok_or("Only programs that are held up can be detached")?;
+        let was_balanced = self.is_balanced(parent);
+        let next_sibling = self.program(id).next_sibling;
+        let before = if self.program(parent).first_child == id.0 {
+            self.programs[parent.0 as usize].first_child = next_sibling;
+            NO_PROGRAM
         } else {
-            // Children nodes deduplicated by total weight.
-            let deduped_children_nodes_with_weights = {
-                let mut t: Vec<_> = self.children_with_weights
-                    .iter()
-                    .map(|cww| (cww.node, cww.total_weight()))
-                    .collect();
-                t.sort_by(|&(_, cww1), &(_, cww2)| cww1.cmp(&cww2));
-                t.dedup_by(|&mut (_, cww1), &mut (_, cww2)| cww1 == cww2);
-                t
-            };
-            // Children nodes deduplicated by total weight, including count of children nodes
-            // that share the same weight
-            let deduped_children_nodes_with_weight_and_counts = {
-                deduped_children_nodes_with_weights
-                    .iter()
-                    .map(|&(child, total_weight)| {
-                        let count = kid_weights
-                            .iter()
-                            .filter(|k| k.total_weight() == total_weight)
-                            .count();
-                        ((child, total_weight), count)
-                    })
+            let before = self.children(parent)
+                .find(|c| self.program(*c).next_sibling == id.0)
+                .ok_or("Program missing from its parent's children")?;
+            self.programs[before.0 as usize].next_sibling = next_sibling;
+            before.0
+        };
+        if self.program(parent).last_child == id.0 {
+            self.programs[parent.0 as usize].last_child = before;
+        }
+        self.programs[id.0 as usize].parent = NO_PROGRAM;
+        self.programs[id.0 as usize].next_sibling = NO_PROGRAM;
+        let total_weight = self.total_weight(id);
+        self.remove_child_total(parent.0, total_weight);
+        let flipped = self.balance_change(parent, was_balanced);
+        let removed = self.program(id).clone();
+        self.update_totals(
+            parent,
+            -(removed.total_weight as isize),
+            flipped - removed.unbalanced as i64,
+        );
+        Ok(())
+    }
+
+    /// Puts a detached program, and everything it holds up, on top of `parent`
+    /// (after anything already there).
+    pub fn attach(&mut self, id: ProgramId, parent: ProgramId) -> Result<(), &'static str> {
+        if id == self.root || self.parent(id).is_some() {
+            return Err("Only detached programs can be attached");
+        }
+        let mut below = Some(parent);
+        while let Some(p) = below {
+            if p == id {
+                return Err("Can't attach a program on top of itself");
+            }
+            below = self.parent(p);
+        }
+        let was_balanced = self.is_balanced(parent);
+        match self.program(parent).last_child {
+            NO_PROGRAM => self.programs[parent.0 as usize].first_child = id.0,
+            last => self.programs[last as usize].next_sibling = id.0,
+        }
+        self.programs[parent.0 as usize].last_child = id.0;
+        self.programs[id.0 as usize].parent = parent.0;
+        let total_weight = self.total_weight(id);
+        self.add_child_total(parent.0, total_weight);
+        let flipped = self.balance_change(parent, was_balanced);
+        let added = self.program(id).clone();
+        self.update_totals(
+            parent,
+            added.total_weight as isize,
+            flipped + added.unbalanced as i64,
+        );
+        Ok(())
+    }
+
+    // 1 if `id` has just become unbalanced, -1 if it has just become balanced
+    fn balance_change(&self, id: ProgramId, was_balanced: bool) -> i64 {
+        was_balanced as i64 - self.is_balanced(id) as i64
+    }
+
+    // Changes the total weight and unbalanced count of `id` and everything
+    // below it, along with the counts for any of those programs that this
+    // balances or unbalances. Each of them only takes a couple of lookups in
+    // child_totals.
+    fn update_totals(&mut self, id: ProgramId, weight_delta: isize, unbalanced_delta: i64) -> () {
+        let mut current = Some(id);
+        let mut unbalanced_delta = unbalanced_delta;
+        while let Some(id) = current {
+            let parent = self.parent(id);
+            let parent_was_balanced = parent.map(|p| self.is_balanced(p));
+            let (old_total, new_total) = {
+                let program = &mut self.programs[id.0 as usize];
+                let old_total = program.total_weight;
+                program.total_weight = (old_total as isize + weight_delta) as usize;
+                program.unbalanced = (program.unbalanced as i64 + unbalanced_delta) as u32;
+                (old_total, program.total_weight)
             };
-            let children_with_unique_weights: Vec<_> =
-                deduped_children_nodes_with_weight_and_counts
-                    .filter(|&(_, count)| count == 1)
-                    .collect();
-            if children_with_unique_weights.len() != 1 {
-                Err("Could not find a singular child node in this tree for adjustment.")
+            if let (Some(p), Some(was_balanced)) = (parent, parent_was_balanced) {
+                self.remove_child_total(p.0, old_total);
+                self.add_child_total(p.0, new_total);
+                unbalanced_delta += self.balance_change(p, was_balanced);
+            }
+            current = parent;
+        }
+    }
+
+    /// Every program reachable from the root, each before what it holds up.
+    fn pre_order(&self) -> Vec<ProgramId> {
+        let mut order = Vec::with_capacity(self.programs.len());
+        let mut to_visit = vec![self.root];
+        while let Some(id) = to_visit.pop() {
+            order.push(id);
+            let first_visit_at = to_visit.len();
+            to_visit.extend(self.children(id));
+            to_visit[first_visit_at..].reverse();
+        }
+        order
+    }
+
+    /// Programs from the root up to `id`.
+    fn path_to(&self, id: ProgramId) -> Vec<ProgramId> {
+        let mut path = vec![id];
+        while let Some(parent) = self.parent(path[path.len() - 1]) {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph tower {\n");
+        let mut to_visit = vec![(self.root, None)];
+        while let Some((id, parent)) = to_visit.pop() {
+            let name = dot_escape(self.name(id));
+            if let Some(parent) = parent {
+                out.push_str(&format!(
+                    "    \"{}\" -> \"{}\";\n",
+                    dot_escape(self.name(parent)),
+                    name
+                ));
+            }
+            let highlight = if self.is_balanced(id) {
+                ""
             } else {
-                let (odd_node, odd_node_total_weight) = children_with_unique_weights[0].0;
-                // Retrieve another node where the total weight (w/ kids) is not the same, then diff
-                if let Some(&(_, other_total_weight)) = deduped_children_nodes_with_weights
-                    .iter()
-                    .find(|&&(_, total_weight)| total_weight != odd_node_total_weight)
-                {
-                    let diff = other_total_weight as isize - odd_node_total_weight as isize;
-                    Ok(odd_node.weight as isize + diff)
+                ", color=red, fontcolor=red"
+            };
+            out.push_str(&format!(
+                "    \"{}\" [label=\"{}\\nweight {}\\ntotal {}\"{}];\n",
+                name,
+                name,
+                self.weight(id),
+                self.total_weight(id),
+                highlight
+            ));
+            let first_visit_at = to_visit.len();
+            to_visit.extend(self.children(id).map(|c| (c, Some(id))));
+            to_visit[first_visit_at..].reverse();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn to_text_tree(&self) -> String {
+        let mut out = String::new();
+        // The first prefix goes in front of the program's own line and the
+        // second in front of everything it holds up.
+        let mut to_visit = vec![(self.root, String::new(), String::new())];
+        while let Some((id, first_prefix, rest_prefix)) = to_visit.pop() {
+            out.push_str(&format!(
+                "{}{} ({}) total {}{}\n",
+                first_prefix,
+                self.name(id),
+                self.weight(id),
+                self.total_weight(id),
+                if self.is_balanced(id) { "" } else { " [unbalanced]" }
+            ));
+            let children: Vec<_> = self.children(id).collect();
+            for child in children.into_iter().rev() {
+                let is_last = self.program(child).next_sibling == NO_PROGRAM;
+                if is_last {
+                    to_visit.push((
+                        child,
+                        format!("{}└── ", rest_prefix),
+                        format!("{}    ", rest_prefix),
+                    ));
                 } else {
-                    Err("No other weights exist.")
+                    to_visit.push((
+                        child,
+                        format!("{}├── ", rest_prefix),
+                        format!("{}│   ", rest_prefix),
+                    ));
                 }
             }
         }
+        out
     }
-}
 
-impl Node {
-    fn from_str(s: &str) -> Result<Node, &'static str> {
-        let entries = NodeEntry::parse(s);
-        Node::from_entries(entries)
+    fn names(&self, path: &[ProgramId]) -> Vec<String> {
+        path.iter().map(|id| self.name(*id).to_string()).collect()
     }
 
-    fn from_entries(entries: Vec<NodeEntry>) -> Result<Node, &'static str> {
-        if let Some(root_entry) = find_root(&entries) {
-            // O(1) access to entries by name.
-            let mut names_to_entries = HashMap::with_capacity(entries.len());
-            for entry in entries.iter() {
-                names_to_entries.insert(&entry.name, entry);
-            }
-            Ok(Node::build(root_entry, &names_to_entries))
-        } else {
-            Err("No root entry found ")
+    fn sibling_totals(&self, id: ProgramId) -> Vec<(String, usize)> {
+        self.children(id)
+            .map(|c| (self.name(c).to_string(), self.total_weight(c)))
+            .collect()
+    }
+
+    fn imbalance(&self, path: &[ProgramId]) -> Imbalance {
+        Imbalance {
+            path: self.names(path),
+            sibling_totals: self.sibling_totals(path[path.len() - 1]),
         }
     }
 
-    fn build(n: &NodeEntry, names_to_entries: &HashMap<&Name, &NodeEntry>) -> Node {
-        let children_nodes: Vec<_> = n.holding_up
-            .iter()
-            .filter_map(|entry_name| {
-                names_to_entries
-                    .get(entry_name)
-                    .map(|node| Node::build(node, names_to_entries))
+    /// A wrong weight unbalances the program holding it up, and every one
+    /// below that, so with a single wrong weight the unbalanced programs form
+    /// one chain. The wrong program sits on top of the last one in the chain,
+    /// and changing its weight has to even out the discs of every program
+    /// along the way.
+    ///
+    /// Only the parts of the tower with unbalanced programs in are looked at.
+    pub fn rebalance_report(&self) -> RebalanceReport {
+        // Unbalanced programs, by how far up the tower they are
+        let mut unbalanced = Vec::new();
+        let mut to_visit = vec![(self.root, 0)];
+        while let Some((id, depth)) = to_visit.pop() {
+            if !self.is_balanced(id) {
+                unbalanced.push((depth, id));
+            }
+            let first_visit_at = to_visit.len();
+            to_visit.extend(
+                self.children(id)
+                    .filter(|c| !self.is_subtree_balanced(*c))
+                    .map(|c| (c, depth + 1)),
+            );
+            to_visit[first_visit_at..].reverse();
+        }
+        unbalanced.sort_by_key(|&(depth, _)| depth);
+        let all_imbalances = || {
+            let imbalances = unbalanced
+                .iter()
+                .map(|&(_, id)| self.imbalance(&self.path_to(id)))
+                .collect();
+            RebalanceReport::MultipleImbalances(imbalances)
+        };
+
+        // Only the path to the deepest is built, however many there are
+        let deepest = match unbalanced.last() {
+            None => return RebalanceReport::Balanced,
+            Some(&(_, id)) => self.path_to(id),
+        };
+        if !unbalanced.iter().all(|&(depth, id)| deepest[depth] == id) {
+            return all_imbalances();
+        }
+
+        let holder = deepest[deepest.len() - 1];
+        let fits_path = |delta: isize| {
+            deepest.windows(2).all(|pair| {
+                let (below, on_path) = (pair[0], pair[1]);
+                let corrected_total = self.total_weight(on_path) as isize + delta;
+                self.children(below)
+                    .filter(|c| *c != on_path)
+                    .all(|c| self.total_weight(c) as isize == corrected_total)
+            })
+        };
+        let candidates: Vec<_> = self.children(holder)
+            .filter_map(|kid| {
+                let others = self.children(holder)
+                    .filter(|other| *other != kid)
+                    .map(|other| self.total_weight(other));
+                let delta = uniform_total(others)? as isize - self.total_weight(kid) as isize;
+                let corrected_weight = self.weight(kid) as isize + delta;
+                if corrected_weight >= 0 && fits_path(delta) {
+                    Some((kid, corrected_weight as usize))
+                } else {
+                    None
+                }
             })
             .collect();
-        Node {
-            name: n.name.clone(),
-            weight: n.weight,
-            children: children_nodes,
+        match candidates.len() {
+            1 => {
+                let (kid, corrected_weight) = candidates[0];
+                let mut path = self.names(&deepest);
+                path.push(self.name(kid).to_string());
+                RebalanceReport::Rebalance(Rebalance {
+                    path,
+                    current_weight: self.weight(kid),
+                    corrected_weight,
+                    sibling_totals: self.sibling_totals(holder),
+                })
+            }
+            2 => RebalanceReport::AmbiguousPair(self.imbalance(&deepest)),
+            _ => all_imbalances(),
+        }
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace("\\", "\\\\").replace("\"", "\\\"")
+}
+
+/// Everything that stops `entries` from making up a single tree.
+fn validate_entries(entries: &Vec<NodeEntry>) -> Vec<TowerProblem> {
+    let mut problems = Vec::new();
+    // Duplicates are reported, and otherwise ignored in favour of the first.
+    let mut names_to_entries: HashMap<&Name, &NodeEntry> = HashMap::with_capacity(entries.len());
+    let mut lines_by_name: HashMap<&Name, Vec<usize>> = HashMap::with_capacity(entries.len());
+    for entry in entries.iter() {
+        names_to_entries.entry(&entry.name).or_insert(entry);
+        lines_by_name.entry(&entry.name).or_insert_with(Vec::new).push(entry.line);
+    }
+    let is_first = |entry: &NodeEntry| ptr::eq(names_to_entries[&entry.name], entry);
+    let mut parent_lines: HashMap<&Name, Vec<usize>> = HashMap::new();
+    for entry in entries.iter() {
+        if lines_by_name[&entry.name].len() > 1 && is_first(entry) {
+            problems.push(TowerProblem::DuplicateName {
+                name: entry.name.0.clone(),
+                lines: lines_by_name[&entry.name].clone(),
+            });
+        }
+        for child in entry.holding_up.iter() {
+            if names_to_entries.contains_key(child) {
+                parent_lines.entry(child).or_insert_with(Vec::new).push(entry.line);
+            } else {
+                problems.push(TowerProblem::MissingChild {
+                    parent: entry.name.0.clone(),
+                    line: entry.line,
+                    child: child.0.clone(),
+                });
+            }
+        }
+    }
+    for entry in entries.iter() {
+        if let Some(lines) = parent_lines.get(&entry.name) {
+            if lines.len() > 1 && is_first(entry) {
+                problems.push(TowerProblem::MultipleParents {
+                    name: entry.name.0.clone(),
+                    lines: lines.clone(),
+                });
+            }
+        }
+    }
+
+    let roots: Vec<&NodeEntry> = entries
+        .iter()
+        .filter(|e| is_first(e) && !parent_lines.contains_key(&e.name))
+        .collect();
+    match roots.len() {
+        0 => problems.push(TowerProblem::NoRoot),
+        1 => (),
+        _ => problems.push(TowerProblem::MultipleRoots {
+            roots: roots.iter().map(|e| (e.name.0.clone(), e.line)).collect(),
+        }),
+    }
+
+    let cycles = find_cycles(entries, &names_to_entries);
+    let in_cycle: HashSet<&Name> = cycles.iter().flat_map(|c| c.iter().map(|e| &e.name)).collect();
+    for cycle in cycles.iter() {
+        problems.push(TowerProblem::Cycle {
+            programs: cycle.iter().map(|e| (e.name.0.clone(), e.line)).collect(),
+        });
+    }
+
+    // Whatever can't be reached from the bottom hangs off a cycle.
+    let mut reachable: HashSet<&Name> = HashSet::with_capacity(entries.len());
+    let mut to_visit: Vec<&Name> = roots.iter().map(|e| &e.name).collect();
+    while let Some(name) = to_visit.pop() {
+        if reachable.insert(name) {
+            if let Some(entry) = names_to_entries.get(name) {
+                to_visit.extend(entry.holding_up.iter());
+            }
         }
     }
+    for entry in entries.iter() {
+        let orphaned_top = !reachable.contains(&entry.name) && !in_cycle.contains(&entry.name)
+            && entries
+                .iter()
+                .any(|p| in_cycle.contains(&p.name) && p.holding_up.contains(&entry.name));
+        if orphaned_top && is_first(entry) {
+            problems.push(TowerProblem::OrphanedSubtree {
+                name: entry.name.0.clone(),
+                line: entry.line,
+            });
+        }
+    }
+    problems
+}
+
+/// Every cycle of programs holding each other up, starting from the one
+/// described first.
+fn find_cycles<'a>(
+    entries: &'a Vec<NodeEntry>,
+    names_to_entries: &HashMap<&'a Name, &'a NodeEntry>,
+) -> Vec<Vec<&'a NodeEntry>> {
+    // Iterative depth first search, where `path` is the way down to the
+    // current entry and `next_child` how far through its children we are.
+    let mut done: HashSet<&Name> = HashSet::with_capacity(entries.len());
+    // Where each entry on the path is on it, to spot cycles quickly.
+    let mut on_path: HashMap<&Name, usize> = HashMap::new();
+    let mut cycles: Vec<Vec<&NodeEntry>> = Vec::new();
+    for start in entries.iter() {
+        if !ptr::eq(names_to_entries[&start.name], start) || done.contains(&start.name) {
+            continue;
+        }
+        let mut path: Vec<(&NodeEntry, usize)> = vec![(start, 0)];
+        on_path.insert(&start.name, 0);
+        while let Some(&(entry, next_child)) = path.last() {
+            match entry.holding_up.get(next_child) {
+                None => {
+                    done.insert(&entry.name);
+                    on_path.remove(&entry.name);
+                    path.pop();
+                }
+                Some(child_name) => {
+                    let top = path.len() - 1;
+                    path[top].1 += 1;
+                    if let Some(child) = names_to_entries.get(child_name) {
+                        match on_path.get(&child.name) {
+                            Some(&cycle_start) => {
+                                let cycle: Vec<_> =
+                                    path[cycle_start..].iter().map(|&(e, _)| e).collect();
+                                // Report each cycle from the entry that comes first
+                                let first = (0..cycle.len())
+                                    .min_by_key(|i| cycle[*i].line)
+                                    .unwrap_or(0);
+                                let (before, after) = cycle.split_at(first);
+                                cycles.push(after.iter().chain(before).map(|e| *e).collect());
+                            }
+                            None if !done.contains(&child.name) => {
+                                on_path.insert(&child.name, path.len());
+                                path.push((child, 0));
+                            }
+                            None => (),
+                        }
+                    }
+                }
+            }
+        }
+    }
+    cycles.sort_by_key(|cycle| cycle[0].line);
+    cycles
 }
 
 fn find_root(entries: &Vec<NodeEntry>) -> Option<&NodeEntry> {
@@ -197,46 +958,59 @@ fn find_root(entries: &Vec<NodeEntry>) -> Option<&NodeEntry> {
 }
 
 impl NodeEntry {
-    fn parse(to_parse: &str) -> Vec<NodeEntry> {
-        // Not sure if we can get multiple matches in a single string.
-        to_parse
-            .trim()
-            .split("\n")
-            .filter_map(|s| {
-                ENTRIES_MATCHER.captures(s).and_then(|captures| {
-                    match (
-                        captures.name(NAME_GROUP),
-                        captures
-                            .name(WEIGHT_GROUP)
-                            .and_then(|ws| ws.as_str().parse().ok()),
-                    ) {
-                        (Some(name), Some(weight)) => {
-                            let others = captures
-                                .name(HOLDING_UP_GROUP)
-                                .map(|c| {
-                                    c.as_str()
-                                        .split(",")
-                                        .map(|s| Name(s.trim().to_string()))
-                                        .collect()
-                                })
-                                .unwrap_or_else(|| vec![]);
-                            Some(NodeEntry {
-                                name: Name(name.as_str().to_string()),
-                                weight: weight,
-                                holding_up: others,
-                            })
-                        }
-                        _ => None,
-                    }
-                })
-            })
-            .collect()
+    /// Parses every line, along with the numbers of those that couldn't be.
+    /// Blank lines are skipped.
+    fn parse_with_problems(to_parse: &str) -> (Vec<NodeEntry>, Vec<TowerProblem>) {
+        let mut entries = Vec::new();
+        let mut problems = Vec::new();
+        for (idx, s) in to_parse.split("\n").enumerate() {
+            if s.trim().is_empty() {
+                continue;
+            }
+            match NodeEntry::parse_line(s, idx + 1) {
+                Some(entry) => entries.push(entry),
+                None => problems.push(TowerProblem::Unparseable { line: idx + 1 }),
+            }
+        }
+        (entries, problems)
+    }
+
+    fn parse_line(s: &str, line: usize) -> Option<NodeEntry> {
+        ENTRIES_MATCHER.captures(s).and_then(|captures| {
+            match (
+                captures.name(NAME_GROUP),
+                captures
+                    .name(WEIGHT_GROUP)
+                    .and_then(|ws| ws.as_str().parse().ok()),
+            ) {
+                (Some(name), Some(weight)) => {
+                    let others = captures
+                        .name(HOLDING_UP_GROUP)
+                        .map(|c| {
+                            c.as_str()
+                                .split(",")
+                                .map(|s| Name(s.trim().to_string()))
+                                .collect()
+                        })
+                        .unwrap_or_else(|| vec![]);
+                    Some(NodeEntry {
+                        name: Name(name.as_str().to_string()),
+                        weight: weight,
+                        holding_up: others,
+                        line: line,
+                    })
+                }
+                _ => None,
+            }
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use day_7::*;
+    use common::SplitMix64;
+    use std::mem;
 
     const TEST_INPUT: &str = r#"
 pbga (66)
@@ -255,7 +1029,7 @@ cntj (57)"#;
 
     #[test]
     fn entries_matcher_regex_test() {
-        let parsed = NodeEntry::parse(DAY_7_INPUT);
+        let parsed = NodeEntry::parse_with_problems(DAY_7_INPUT).0;
         assert!(parsed.len() > 0);
         for p in parsed {
             println!("{:?}", p);
@@ -264,31 +1038,496 @@ cntj (57)"#;
 
     #[test]
     fn find_root_test() {
-        let parsed = NodeEntry::parse(TEST_INPUT);
+        let parsed = NodeEntry::parse_with_problems(TEST_INPUT).0;
         let p = find_root(&parsed).unwrap();
         assert_eq!(p.name, Name("tknk".to_string()));
     }
 
     #[test]
     fn node_parse_test() {
-        let tree = Node::from_str(TEST_INPUT).unwrap();
-        println!("{:?}", tree);
-        assert_eq!(tree.name, Name("tknk".to_string()));
+        let tower = Tower::from_str(TEST_INPUT).unwrap();
+        let root = tower.root();
+        assert_eq!(tower.name(root), "tknk");
+        assert_eq!(tower.weight(root), 41);
+        assert_eq!(tower.total_weight(root), 778);
+        assert_eq!(tower.parent(root), None);
+        let children: Vec<_> = tower.children(root).map(|c| tower.name(c)).collect();
+        assert_eq!(children, vec!["ugml", "padx", "fwft"]);
+        let padx = tower.find("padx").unwrap();
+        assert_eq!(tower.parent(padx), Some(root));
+        assert_eq!(tower.total_weight(padx), 243);
+        assert!(tower.is_balanced(padx));
+        assert!(!tower.is_balanced(root));
+        assert_eq!(tower.find("nope"), None);
+    }
+
+    fn report(s: &str) -> RebalanceReport {
+        rebalance_report(s).unwrap()
+    }
+
+    fn totals(totals: &[(&str, usize)]) -> Vec<(String, usize)> {
+        totals.iter().map(|&(n, t)| (n.to_string(), t)).collect()
+    }
+
+    fn path(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn rebalance_report_dry_test() {
+        assert_eq!(
+            report(TEST_INPUT),
+            RebalanceReport::Rebalance(Rebalance {
+                path: path(&["tknk", "ugml"]),
+                current_weight: 68,
+                corrected_weight: 60,
+                sibling_totals: totals(&[("ugml", 251), ("padx", 243), ("fwft", 243)]),
+            })
+        );
+        assert_eq!(
+            report(TEST_INPUT).to_string(),
+            "tknk -> ugml weighs 68 but should weigh 60 \
+             (sibling totals: ugml 251, padx 243, fwft 243)"
+        );
+    }
+
+    #[test]
+    fn rebalance_report_balanced_test() {
+        assert_eq!(report("a (1) -> b, c\nb (2)\nc (2)"), RebalanceReport::Balanced);
+        assert_eq!(report("a (1)"), RebalanceReport::Balanced);
+    }
+
+    #[test]
+    fn rebalance_report_deep_test() {
+        // d is too heavy, which also makes b's disc heavier than c's
+        let input = "a (1) -> b, c\nb (1) -> d, e, f\nc (7)\nd (3)\ne (2)\nf (2)";
+        assert_eq!(
+            report(input),
+            RebalanceReport::Rebalance(Rebalance {
+                path: path(&["a", "b", "d"]),
+                current_weight: 3,
+                corrected_weight: 2,
+                sibling_totals: totals(&[("d", 3), ("e", 2), ("f", 2)]),
+            })
+        );
+    }
+
+    #[test]
+    fn rebalance_report_pair_test() {
+        assert_eq!(
+            report("a (1) -> b, c\nb (2)\nc (3)"),
+            RebalanceReport::AmbiguousPair(Imbalance {
+                path: path(&["a"]),
+                sibling_totals: totals(&[("b", 2), ("c", 3)]),
+            })
+        );
+        // The unbalanced program below says which of the pair is wrong
+        let input = "a (1) -> b, x, y\nb (2) -> c, d\nc (2)\nd (3)\nx (6)\ny (6)";
+        assert_eq!(
+            report(input),
+            RebalanceReport::Rebalance(Rebalance {
+                path: path(&["a", "b", "d"]),
+                current_weight: 3,
+                corrected_weight: 2,
+                sibling_totals: totals(&[("c", 2), ("d", 3)]),
+            })
+        );
     }
 
     #[test]
-    fn smallest_rebalanced_children_weight_dry_test() {
-        let tree = Node::from_str(TEST_INPUT).unwrap();
-        let with_kids_weights = NodeWithChildrenWeight::build(&tree);
-        let rebalance = with_kids_weights.smallest_rebalanced_children_weight();
-        assert_eq!(rebalance, Ok(60));
+    fn rebalance_report_multiple_test() {
+        // Two separate unbalanced programs
+        let input = "a (1) -> b, c\nb (1) -> d, e, f\nc (1) -> g, h, i\n\
+                     d (2)\ne (2)\nf (3)\ng (3)\nh (2)\ni (2)";
+        assert_eq!(
+            report(input),
+            RebalanceReport::MultipleImbalances(vec![
+                Imbalance {
+                    path: path(&["a", "b"]),
+                    sibling_totals: totals(&[("d", 2), ("e", 2), ("f", 3)]),
+                },
+                Imbalance {
+                    path: path(&["a", "c"]),
+                    sibling_totals: totals(&[("g", 3), ("h", 2), ("i", 2)]),
+                },
+            ])
+        );
+        // No two discs weigh the same
+        let input = "a (1) -> b, c, d\nb (1)\nc (2)\nd (3)";
+        assert_eq!(
+            report(input),
+            RebalanceReport::MultipleImbalances(vec![Imbalance {
+                path: path(&["a"]),
+                sibling_totals: totals(&[("b", 1), ("c", 2), ("d", 3)]),
+            }])
+        );
+        assert!(report(input).to_string().starts_with(
+            "No single weight change balances the tower. Unbalanced programs:\n  a (totals:"
+        ));
+    }
+
+    #[test]
+    fn rebalance_report_real_test() {
+        match report(DAY_7_INPUT) {
+            RebalanceReport::Rebalance(r) => assert_eq!(r.corrected_weight, 2310),
+            other => panic!("Unexpected report: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tower_to_text_tree_test() {
+        let rendered = tower_to_text_tree(TEST_INPUT).unwrap();
+        assert_eq!(
+            rendered,
+            "tknk (41) total 778 [unbalanced]
+├── ugml (68) total 251
+│   ├── gyxo (61) total 61
+│   ├── ebii (61) total 61
+│   └── jptl (61) total 61
+├── padx (45) total 243
+│   ├── pbga (66) total 66
+│   ├── havc (66) total 66
+│   └── qoyq (66) total 66
+└── fwft (72) total 243
+    ├── ktlj (57) total 57
+    ├── cntj (57) total 57
+    └── xhth (57) total 57
+"
+        );
     }
 
     #[test]
-    fn smallest_rebalanced_children_weight_real_test() {
-        let tree = Node::from_str(DAY_7_INPUT).unwrap();
-        let with_kids_weights = NodeWithChildrenWeight::build(&tree);
-        let rebalance = with_kids_weights.smallest_rebalanced_children_weight();
-        assert_eq!(rebalance, Ok(2310));
+    fn tower_to_dot_test() {
+        let rendered = tower_to_dot("a (1) -> b\nb (2)").unwrap();
+        assert_eq!(
+            rendered,
+            r#"digraph tower {
+    "a" [label="a\nweight 1\ntotal 3"];
+    "a" -> "b";
+    "b" [label="b\nweight 2\ntotal 2"];
+}
+"#
+        );
+        let dot = tower_to_dot(TEST_INPUT).unwrap();
+        assert!(dot.contains(r#""tknk" [label="tknk\nweight 41\ntotal 778", color=red"#));
+        assert!(dot.contains(r#""padx" [label="padx\nweight 45\ntotal 243"];"#));
+        assert_eq!(dot.matches(" -> ").count(), 12);
+    }
+
+    #[test]
+    fn dot_escape_test() {
+        assert_eq!(dot_escape(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+
+    #[test]
+    fn parse_line_numbers_test() {
+        let parsed = NodeEntry::parse_with_problems(TEST_INPUT).0;
+        assert_eq!(parsed[0].line, 2);
+        assert_eq!(parsed[parsed.len() - 1].line, 14);
+    }
+
+    fn problems(s: &str) -> Vec<TowerProblem> {
+        validate_tower(s).unwrap_err().0
+    }
+
+    #[test]
+    fn validate_tower_ok_test() {
+        assert_eq!(validate_tower(TEST_INPUT), Ok(()));
+        assert_eq!(validate_tower(DAY_7_INPUT), Ok(()));
+    }
+
+    #[test]
+    fn validate_tower_unparseable_and_missing_test() {
+        let input = "a (1) -> b, c\nwhat is this\n\nb (2)";
+        assert_eq!(
+            problems(input),
+            vec![
+                TowerProblem::Unparseable { line: 2 },
+                TowerProblem::MissingChild {
+                    parent: "a".to_string(),
+                    line: 1,
+                    child: "c".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_tower_duplicates_and_roots_test() {
+        let input = "a (1) -> b\nb (2)\nb (3)\nc (4) -> d\nd (5)\ne (6) -> d";
+        assert_eq!(
+            problems(input),
+            vec![
+                TowerProblem::DuplicateName {
+                    name: "b".to_string(),
+                    lines: vec![2, 3],
+                },
+                TowerProblem::MultipleParents {
+                    name: "d".to_string(),
+                    lines: vec![4, 6],
+                },
+                TowerProblem::MultipleRoots {
+                    roots: vec![
+                        ("a".to_string(), 1),
+                        ("c".to_string(), 4),
+                        ("e".to_string(), 6),
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_tower_cycle_test() {
+        let input = "root (1) -> a\nb (2) -> c, x\na (3)\nc (4) -> b\n\
+                     x (5) -> y\ny (6)\nz (7) -> z";
+        let found = problems(input);
+        assert_eq!(
+            found,
+            vec![
+                TowerProblem::Cycle {
+                    programs: vec![("b".to_string(), 2), ("c".to_string(), 4)],
+                },
+                TowerProblem::Cycle {
+                    programs: vec![("z".to_string(), 7)],
+                },
+                TowerProblem::OrphanedSubtree {
+                    name: "x".to_string(),
+                    line: 5,
+                },
+            ]
+        );
+        assert_eq!(
+            InvalidTower(found).to_string(),
+            "Invalid tower:
+programs hold each other up: b (line 2) -> c (line 4) -> b
+programs hold each other up: z (line 7) -> z
+line 5: x is only held up by programs that hold each other up"
+        );
+        assert_eq!(
+            problems("a (1) -> b\nb (1) -> a"),
+            vec![
+                TowerProblem::NoRoot,
+                TowerProblem::Cycle {
+                    programs: vec![("a".to_string(), 1), ("b".to_string(), 2)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_tower_not_built_test() {
+        assert!(Tower::from_str("a (1) -> b\nb (1) -> a").is_err());
+        assert!(tower_to_text_tree("a (1) -> b").is_err());
+    }
+
+    #[test]
+    fn deep_tower_test() {
+        // Far taller than the stack could cope with recursively
+        let height = 100_000;
+        let mut input = String::new();
+        for level in 0..height - 1 {
+            input.push_str(&format!("p{} (1) -> p{}\n", level, level + 1));
+        }
+        input.push_str(&format!("p{} (1)", height - 1));
+        let tower = Tower::from_str(&input).unwrap();
+        assert_eq!(tower.name(tower.root()), "p0");
+        assert_eq!(tower.total_weight(tower.root()), height);
+        assert_eq!(tower.rebalance_report(), RebalanceReport::Balanced);
+        assert_eq!(tower.to_dot().matches(" -> ").count(), height - 1);
+    }
+
+    #[test]
+    fn deep_unbalanced_tower_test() {
+        // With nothing else weighing anything, one wrong weight at the top
+        // unbalances every program below it.
+        let height = 100_000;
+        let mut input = String::new();
+        for level in 0..height - 1 {
+            input.push_str(&format!("p{} (0) -> p{}, l{}\n", level, level + 1, level));
+            input.push_str(&format!("l{} (0)\n", level));
+        }
+        input.push_str(&format!("p{} (0) -> a, b\na (0)\nb (1)", height - 1));
+        let tower = Tower::from_str(&input).unwrap();
+        assert!(!tower.is_balanced(tower.root()));
+        match tower.rebalance_report() {
+            RebalanceReport::Rebalance(r) => {
+                assert_eq!(r.path.len(), height + 1);
+                assert_eq!(r.path[0], "p0");
+                assert_eq!(r.path[height], "b");
+                assert_eq!((r.current_weight, r.corrected_weight), (1, 0));
+            }
+            other => panic!("Expected a rebalance, got {}", other),
+        }
+    }
+
+    #[test]
+    fn program_size_test() {
+        // Each program used to take one of each of these, and a String of its
+        // own for the name
+        #[allow(dead_code)]
+        struct Node {
+            name: Name,
+            weight: usize,
+            children: Vec<Node>,
+        }
+        #[allow(dead_code)]
+        struct NodeWithChildrenWeight<'a> {
+            node: &'a Node,
+            children_with_weights: Vec<NodeWithChildrenWeight<'a>>,
+            children_weight: usize,
+        }
+        let tower = Tower::from_str(DAY_7_INPUT).unwrap();
+        let count = tower.programs.len();
+        let old_size = count * (mem::size_of::<Node>() + mem::size_of::<NodeWithChildrenWeight>())
+            + tower.names.len();
+        // Now there's the program itself and its name, plus the counts in
+        // child_totals (with a byte or so each of hash table overhead) for the
+        // programs holding others up
+        let child_total_size = mem::size_of::<((u32, usize), u32)>() + 1;
+        let new_size = tower.programs.capacity() * mem::size_of::<Program>()
+            + tower.names.capacity()
+            + tower.child_totals.capacity() * child_total_size;
+        assert!(new_size * 4 < old_size * 3);
+    }
+
+    #[test]
+    fn set_weight_test() {
+        let mut tower = Tower::from_str(TEST_INPUT).unwrap();
+        let root = tower.root();
+        let ugml = tower.find("ugml").unwrap();
+        assert!(!tower.is_subtree_balanced(root));
+        assert!(tower.is_subtree_balanced(ugml));
+        tower.set_weight(ugml, 60);
+        assert_eq!(tower.total_weight(root), 770);
+        assert!(tower.is_subtree_balanced(root));
+        assert_eq!(tower.rebalance_report(), RebalanceReport::Balanced);
+        // Unbalance it again, further up
+        let ktlj = tower.find("ktlj").unwrap();
+        tower.set_weight(ktlj, 50);
+        assert!(!tower.is_subtree_balanced(tower.find("fwft").unwrap()));
+        match tower.rebalance_report() {
+            RebalanceReport::Rebalance(r) => {
+                assert_eq!(r.path, path(&["tknk", "fwft", "ktlj"]));
+                assert_eq!(r.corrected_weight, 57);
+            }
+            other => panic!("Unexpected report: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn detach_attach_test() {
+        let mut tower = Tower::from_str(TEST_INPUT).unwrap();
+        let root = tower.root();
+        let ugml = tower.find("ugml").unwrap();
+        let padx = tower.find("padx").unwrap();
+        tower.detach(ugml).unwrap();
+        assert_eq!(tower.parent(ugml), None);
+        assert_eq!(tower.total_weight(root), 527);
+        assert!(tower.is_subtree_balanced(root));
+        let children: Vec<_> = tower.children(root).map(|c| tower.name(c)).collect();
+        assert_eq!(children, vec!["padx", "fwft"]);
+
+        tower.attach(ugml, padx).unwrap();
+        assert_eq!(tower.total_weight(padx), 494);
+        assert_eq!(tower.total_weight(root), 778);
+        assert!(!tower.is_balanced(padx));
+        assert!(!tower.is_subtree_balanced(root));
+        let children: Vec<_> = tower.children(padx).map(|c| tower.name(c)).collect();
+        assert_eq!(children, vec!["pbga", "havc", "qoyq", "ugml"]);
+
+        let extra = tower.add_program("extra", 5);
+        assert_eq!(tower.name(extra), "extra");
+        tower.attach(extra, tower.find("pbga").unwrap()).unwrap();
+        assert_eq!(tower.total_weight(root), 783);
+    }
+
+    #[test]
+    fn detach_attach_errors_test() {
+        let mut tower = Tower::from_str(TEST_INPUT).unwrap();
+        let root = tower.root();
+        let padx = tower.find("padx").unwrap();
+        let pbga = tower.find("pbga").unwrap();
+        assert!(tower.detach(root).is_err());
+        assert!(tower.attach(padx, root).is_err());
+        assert!(tower.attach(root, padx).is_err());
+        tower.detach(padx).unwrap();
+        assert!(tower.detach(padx).is_err());
+        assert!(tower.attach(padx, pbga).is_err());
+        assert!(tower.attach(padx, padx).is_err());
+    }
+
+    // Checks the stored totals and counts against working them out afresh.
+    fn assert_consistent(tower: &Tower) {
+        for idx in 0..tower.programs.len() {
+            let id = ProgramId(idx as u32);
+            let children: Vec<_> = tower.children(id).collect();
+            let total = children
+                .iter()
+                .fold(tower.weight(id), |acc, c| acc + tower.total_weight(*c));
+            let own_unbalanced = if tower.is_balanced(id) { 0 } else { 1 };
+            let unbalanced = children
+                .iter()
+                .fold(own_unbalanced, |acc, c| acc + tower.program(*c).unbalanced);
+            let mut totals = children.iter().map(|c| tower.total_weight(*c));
+            let balanced = match totals.next() {
+                None => true,
+                Some(first) => totals.all(|t| t == first),
+            };
+            assert_eq!(tower.is_balanced(id), balanced, "{}", tower.name(id));
+            assert_eq!(
+                tower.program(id).last_child,
+                children.last().map(|c| c.0).unwrap_or(NO_PROGRAM)
+            );
+            assert_eq!(tower.total_weight(id), total, "{}", tower.name(id));
+            assert_eq!(tower.program(id).unbalanced, unbalanced, "{}", tower.name(id));
+            for child in children {
+                assert_eq!(tower.parent(child), Some(id));
+            }
+        }
+    }
+
+    #[test]
+    fn dynamic_tower_differential_test() {
+        let mut rng = SplitMix64(7);
+        let mut tower = Tower::new("p0", 1);
+        for idx in 1..60 {
+            let id = tower.add_program(&format!("p{}", idx), 1 + rng.below(3));
+            let parent = ProgramId(rng.below(idx) as u32);
+            tower.attach(id, parent).unwrap();
+        }
+        assert_consistent(&tower);
+        for _ in 0..2000 {
+            let id = ProgramId(rng.below(60) as u32);
+            match rng.below(3) {
+                0 => tower.set_weight(id, 1 + rng.below(3)),
+                1 => {
+                    let _ = tower.detach(id);
+                }
+                _ => {
+                    let parent = ProgramId(rng.below(60) as u32);
+                    let _ = tower.attach(id, parent);
+                }
+            }
+            assert_consistent(&tower);
+        }
+        // Put everything back on the tower and compare with building it afresh
+        for idx in 1..60 {
+            let _ = tower.attach(ProgramId(idx), tower.root());
+        }
+        let description: Vec<_> = (0..60)
+            .map(|idx| {
+                let id = ProgramId(idx);
+                let children: Vec<_> = tower.children(id).map(|c| tower.name(c)).collect();
+                if children.is_empty() {
+                    format!("{} ({})", tower.name(id), tower.weight(id))
+                } else {
+                    format!("{} ({}) -> {}", tower.name(id), tower.weight(id), children.join(", "))
+                }
+            })
+            .collect();
+        let rebuilt = Tower::from_str(&description.join("\n")).unwrap();
+        assert_eq!(rebuilt, tower);
+        assert_eq!(rebuilt.rebalance_report(), tower.rebalance_report());
     }
 }
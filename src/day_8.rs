@@ -1,5 +1,8 @@
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 use combine::char::*;
 use combine::primitives::*;
 use combine::*;
@@ -14,24 +17,34 @@ pub fn run() -> Result<(), &'static str> {
     Ok(())
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct Instruction {
-    register: String,
-    op: Op,
-    amount: i64,
-    check_register: String,
-    cond: Cond,
-    cond_amount: i64,
+/// `<register> <op> <amount> [if <condition>]`, where a missing condition
+/// always holds.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Instruction {
+    pub register: String,
+    pub op: Op,
+    pub amount: Operand,
+    pub condition: Option<Condition>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum Op {
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Op {
     Inc,
     Dec,
+    Set,
+    Mul,
+    Div,
+    Mod,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum Cond {
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Operand {
+    Register(String),
+    Number(i64),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Cond {
     GT,
     LT,
     GTE,
@@ -40,6 +53,18 @@ enum Cond {
     NE,
 }
 
+/// `and` binds more tightly than `or`, and both group to the left.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Condition {
+    Compare {
+        left: Operand,
+        cond: Cond,
+        right: Operand,
+    },
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
 impl Cond {
     fn compare(&self, target: i64, amount: i64) -> bool {
         match self {
@@ -53,20 +78,334 @@ impl Cond {
     }
 }
 
+impl Op {
+    /// What the register ends up holding; integer division rounds towards 0,
+    /// and `mod` takes the sign of the register.
+    fn apply(&self, current: i64, amount: i64) -> Result<i64, &'static str> {
+        let result = match self {
+            &Op::Inc => current.checked_add(amount),
+            &Op::Dec => current.checked_sub(amount),
+            &Op::Set => Some(amount),
+            &Op::Mul => current.checked_mul(amount),
+            &Op::Div | &Op::Mod if amount == 0 => return Err("division by zero"),
+            &Op::Div => current.checked_div(amount),
+            &Op::Mod => current.checked_rem(amount),
+        };
+        result.ok_or("overflow")
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            &Op::Inc => "inc",
+            &Op::Dec => "dec",
+            &Op::Set => "set",
+            &Op::Mul => "mul",
+            &Op::Div => "div",
+            &Op::Mod => "mod",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Operand::Register(ref name) => write!(f, "{}", name),
+            &Operand::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            &Cond::GT => ">",
+            &Cond::LT => "<",
+            &Cond::GTE => ">=",
+            &Cond::LTE => "<=",
+            &Cond::E => "==",
+            &Cond::NE => "!=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Condition::Compare {
+                ref left,
+                cond,
+                ref right,
+            } => write!(f, "{} {} {}", left, cond, right),
+            &Condition::And(ref a, ref b) => write!(f, "{} and {}", a, b),
+            &Condition::Or(ref a, ref b) => write!(f, "{} or {}", a, b),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.register, self.op, self.amount)?;
+        match self.condition {
+            Some(ref condition) => write!(f, " if {}", condition),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Register names, each given a dense index in the order they're first seen.
+#[derive(Debug, Default, Clone)]
+struct Registers {
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl Registers {
+    fn intern(&mut self, name: &str) -> usize {
+        if let Some(idx) = self.indices.get(name) {
+            return *idx;
+        }
+        let idx = self.names.len();
+        self.names.push(name.to_string());
+        self.indices.insert(name.to_string(), idx);
+        idx
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CompiledOperand {
+    Register(usize),
+    Number(i64),
+}
+
+#[derive(Debug, Clone)]
+enum CompiledCondition {
+    Compare {
+        left: CompiledOperand,
+        cond: Cond,
+        right: CompiledOperand,
+    },
+    And(Box<CompiledCondition>, Box<CompiledCondition>),
+    Or(Box<CompiledCondition>, Box<CompiledCondition>),
+}
+
+#[derive(Debug, Clone)]
+struct CompiledInstruction {
+    register: usize,
+    op: Op,
+    amount: CompiledOperand,
+    condition: Option<CompiledCondition>,
+}
+
+impl CompiledOperand {
+    // Registers we've never heard of have never been written to, so read as 0
+    fn new(operand: &Operand, registers: &HashMap<String, usize>) -> CompiledOperand {
+        match operand {
+            &Operand::Register(ref name) => match registers.get(name) {
+                Some(idx) => CompiledOperand::Register(*idx),
+                None => CompiledOperand::Number(0),
+            },
+            &Operand::Number(n) => CompiledOperand::Number(n),
+        }
+    }
+
+    fn value(&self, values: &[i64]) -> i64 {
+        match self {
+            &CompiledOperand::Register(idx) => values[idx],
+            &CompiledOperand::Number(n) => n,
+        }
+    }
+}
+
+impl CompiledCondition {
+    fn new(condition: &Condition, registers: &HashMap<String, usize>) -> CompiledCondition {
+        let compile = |c: &Condition| Box::new(CompiledCondition::new(c, registers));
+        match condition {
+            &Condition::Compare {
+                ref left,
+                cond,
+                ref right,
+            } => CompiledCondition::Compare {
+                left: CompiledOperand::new(left, registers),
+                cond,
+                right: CompiledOperand::new(right, registers),
+            },
+            &Condition::And(ref a, ref b) => CompiledCondition::And(compile(a), compile(b)),
+            &Condition::Or(ref a, ref b) => CompiledCondition::Or(compile(a), compile(b)),
+        }
+    }
+
+    fn holds(&self, values: &[i64]) -> bool {
+        match self {
+            &CompiledCondition::Compare { left, cond, right } => {
+                cond.compare(left.value(values), right.value(values))
+            }
+            &CompiledCondition::And(ref a, ref b) => a.holds(values) && b.holds(values),
+            &CompiledCondition::Or(ref a, ref b) => a.holds(values) || b.holds(values),
+        }
+    }
+}
+
+/// A parsed program, with its register names interned so that running it
+/// doesn't need to hash anything.
+#[derive(Debug, Clone)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    registers: Registers,
+    compiled: Vec<CompiledInstruction>,
+}
+
+impl Program {
+    /// Parses a program, one instruction per line.
+    pub fn parse(s: &str) -> Result<Program, String> {
+        parse_instructions(s).map(Program::new)
+    }
+
+    pub fn new(instructions: Vec<Instruction>) -> Program {
+        let mut registers = Registers::default();
+        for i in instructions.iter() {
+            registers.intern(&i.register);
+            let mut operands = vec![&i.amount];
+            if let Some(ref condition) = i.condition {
+                push_operands(condition, &mut operands);
+            }
+            for operand in operands {
+                if let &Operand::Register(ref name) = operand {
+                    registers.intern(name);
+                }
+            }
+        }
+        let compiled = instructions
+            .iter()
+            .map(|i| CompiledInstruction {
+                register: registers.indices[&i.register],
+                op: i.op,
+                amount: CompiledOperand::new(&i.amount, &registers.indices),
+                condition: i.condition
+                    .as_ref()
+                    .map(|c| CompiledCondition::new(c, &registers.indices)),
+            })
+            .collect();
+        Program {
+            instructions,
+            registers,
+            compiled,
+        }
+    }
+
+    /// The instructions as parsed, in program order.
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Every register the program mentions, in the order they first appear.
+    pub fn register_names(&self) -> &[String] {
+        &self.registers.names
+    }
+}
+
+fn push_operands<'a>(condition: &'a Condition, operands: &mut Vec<&'a Operand>) -> () {
+    match condition {
+        &Condition::Compare {
+            ref left,
+            ref right,
+            ..
+        } => {
+            operands.push(left);
+            operands.push(right);
+        }
+        &Condition::And(ref a, ref b) | &Condition::Or(ref a, ref b) => {
+            push_operands(a, operands);
+            push_operands(b, operands);
+        }
+    }
+}
+
 struct Simulation<'a> {
-    instructions: &'a Vec<Instruction>,
-    registers: HashMap<&'a str, i64>,
+    program: &'a Program,
+    values: Vec<i64>,
+    written: Vec<bool>,
     historical_highest_reg_value: Option<i64>,
     current_highest_reg_value: Option<i64>,
+    // How many written registers hold each value. A plain running maximum
+    // isn't enough, since `set`, `div` and `mod` can lower the register that
+    // holds it, and then the next highest has to come from somewhere.
+    written_values: BTreeMap<i64, usize>,
+    next_idx: usize,
 }
 
 impl<'a> Simulation<'a> {
-    fn new(instructions: &'a Vec<Instruction>) -> Simulation<'a> {
+    fn new(program: &'a Program) -> Simulation<'a> {
+        let register_count = program.registers.names.len();
         Simulation {
-            instructions: instructions,
-            registers: HashMap::new(),
+            program,
+            values: vec![0; register_count],
+            written: vec![false; register_count],
             historical_highest_reg_value: None,
             current_highest_reg_value: None,
+            written_values: BTreeMap::new(),
+            next_idx: 0,
+        }
+    }
+
+    fn step(&mut self) -> Option<Result<Step<'a>, String>> {
+        let idx = self.next_idx;
+        let i = self.program.compiled.get(idx)?;
+        self.next_idx += 1;
+        let condition_passed = match i.condition {
+            Some(ref condition) => condition.holds(&self.values),
+            None => true,
+        };
+        let before = self.values[i.register];
+        let mut after = before;
+        if condition_passed {
+            let amount = i.amount.value(&self.values);
+            after = match i.op.apply(before, amount) {
+                Ok(v) => v,
+                Err(e) => {
+                    let instruction = &self.program.instructions[idx];
+                    return Some(Err(format!("Instruction {} ({}): {}", idx + 1, instruction, e)));
+                }
+            };
+            self.write(i.register, after);
+        }
+        Some(Ok(Step {
+            idx,
+            instruction: &self.program.instructions[idx],
+            condition_passed,
+            before,
+            after,
+            current_highest_reg_value: self.current_highest_reg_value,
+            historical_highest_reg_value: self.historical_highest_reg_value,
+            triggered_watch: None,
+        }))
+    }
+
+    // Keeps the highest value up to date as we go, in O(log registers) per
+    // write rather than O(1), for the multiset in `written_values`
+    fn write(&mut self, register: usize, value: i64) -> () {
+        if self.written[register] {
+            let old_value = self.values[register];
+            let now_unused = match self.written_values.get_mut(&old_value) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                None => false,
+            };
+            if now_unused {
+                self.written_values.remove(&old_value);
+            }
+        }
+        self.values[register] = value;
+        self.written[register] = true;
+        *self.written_values.entry(value).or_insert(0) += 1;
+        self.current_highest_reg_value = self.written_values.keys().next_back().cloned();
+        if self.current_highest_reg_value > self.historical_highest_reg_value {
+            self.historical_highest_reg_value = self.current_highest_reg_value;
         }
     }
 }
@@ -77,106 +416,613 @@ struct SimulationResult {
     current_highest_reg_value: Option<i64>,
 }
 
-fn run_simulation(s: &mut Simulation) -> () {
-    s.instructions.iter().fold(s, |simulation, i| {
-        // arg borrow checker..
-        let should_proceed = {
-            let check_register_value = simulation
+/// What happened when running one instruction.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Step<'a> {
+    /// Index of the instruction in the program.
+    pub idx: usize,
+    pub instruction: &'a Instruction,
+    pub condition_passed: bool,
+    /// The instruction's register before and after running it.
+    pub before: i64,
+    pub after: i64,
+    /// Highest value in any register that has been written to, now and at any
+    /// point so far.
+    pub current_highest_reg_value: Option<i64>,
+    pub historical_highest_reg_value: Option<i64>,
+    /// Index of the first watch that held after this step, which is the last.
+    pub triggered_watch: Option<usize>,
+}
+
+impl<'a> Step<'a> {
+    /// How much the register changed by, if that fits in an i64.
+    pub fn delta(&self) -> Option<i64> {
+        self.after.checked_sub(self.before)
+    }
+}
+
+/// Everything about a simulation at some point.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Snapshot {
+    /// How many instructions have been run.
+    pub executed: usize,
+    /// Registers that have been written to.
+    pub registers: BTreeMap<String, i64>,
+    pub current_highest_reg_value: Option<i64>,
+    pub historical_highest_reg_value: Option<i64>,
+}
+
+/// Runs a program one instruction at a time.
+///
+/// Stops early after a step that fails, or that leaves any of the watched
+/// conditions holding.
+pub struct Steps<'a> {
+    simulation: Simulation<'a>,
+    watches: Vec<CompiledCondition>,
+    stopped: bool,
+}
+
+impl<'a> Steps<'a> {
+    pub fn new(program: &'a Program) -> Steps<'a> {
+        Steps {
+            simulation: Simulation::new(program),
+            watches: Vec::new(),
+            stopped: false,
+        }
+    }
+
+    /// Stops after any step that leaves `condition` holding, e.g. `x > 100`.
+    pub fn watch(mut self, condition: Condition) -> Steps<'a> {
+        let indices = &self.simulation.program.registers.indices;
+        self.watches.push(CompiledCondition::new(&condition, indices));
+        self
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            executed: self.simulation.next_idx,
+            registers: self.simulation
+                .program
                 .registers
-                .get(i.check_register.as_str())
-                .unwrap_or(&0);
-            i.cond.compare(*check_register_value, i.cond_amount)
-        };
-        if should_proceed {
-            match i.op {
-                Op::Inc => {
-                    *simulation.registers.entry(i.register.as_str()).or_insert(0) += i.amount
-                }
-                Op::Dec => {
-                    *simulation.registers.entry(i.register.as_str()).or_insert(0) -= i.amount
-                }
-            }
+                .names
+                .iter()
+                .zip(self.simulation.values.iter())
+                .zip(self.simulation.written.iter())
+                .filter(|&(_, written)| *written)
+                .map(|((name, value), _)| (name.clone(), *value))
+                .collect(),
+            current_highest_reg_value: self.simulation.current_highest_reg_value,
+            historical_highest_reg_value: self.simulation.historical_highest_reg_value,
+        }
+    }
+}
+
+impl<'a> Iterator for Steps<'a> {
+    type Item = Result<Step<'a>, String>;
+
+    fn next(&mut self) -> Option<Result<Step<'a>, String>> {
+        if self.stopped {
+            return None;
         }
-        let max_value_in_reg = max_value(&simulation.registers);
-        simulation.current_highest_reg_value = max_value_in_reg;
-        match max_value_in_reg {
+        let mut step = self.simulation.step();
+        match step {
+            Some(Ok(ref mut step)) => {
+                let values = &self.simulation.values;
+                step.triggered_watch = self.watches.iter().position(|w| w.holds(values));
+                self.stopped = step.triggered_watch.is_some();
+            }
+            Some(Err(_)) => self.stopped = true,
             None => (),
-            Some(v) => match simulation.historical_highest_reg_value {
-                Some(old_highest_reg_value) => if v > old_highest_reg_value {
-                    simulation.historical_highest_reg_value = Some(v)
-                },
-                None => simulation.historical_highest_reg_value = Some(v),
-            },
         }
-        simulation
-    });
+        step
+    }
 }
 
-fn simualate_instructions(s: &str) -> Result<SimulationResult, Errors<PointerOffset, char, &str>> {
-    let (instructions, _) = Instruction::parse(s)?;
-    let mut simulation = Simulation::new(&instructions);
-    run_simulation(&mut simulation);
+fn run_simulation(s: &mut Simulation) -> Result<(), String> {
+    while let Some(step) = s.step() {
+        step?;
+    }
+    Ok(())
+}
+
+fn simualate_instructions(s: &str) -> Result<SimulationResult, String> {
+    let program = Program::parse(s)?;
+    let mut simulation = Simulation::new(&program);
+    run_simulation(&mut simulation)?;
     Ok(SimulationResult {
         current_highest_reg_value: simulation.current_highest_reg_value,
         historical_highest_reg_value: simulation.historical_highest_reg_value,
     })
 }
 
-fn max_value<'a, K, V>(hash: &'a HashMap<K, V>) -> Option<V>
+/// Parses a program, one instruction per line.
+pub fn parse_instructions(s: &str) -> Result<Vec<Instruction>, String> {
+    Instruction::parse(s)
+        .map(|(instructions, _)| instructions)
+        .map_err(|e| e.to_string())
+}
+
+/// The smallest and largest values something can hold.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Interval {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Interval {
+    fn exactly(n: i64) -> Interval {
+        Interval { min: n, max: n }
+    }
+
+    fn join(&self, other: &Interval) -> Interval {
+        Interval {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    fn narrow(&self, min: i64, max: i64) -> Option<Interval> {
+        let narrowed = Interval {
+            min: self.min.max(min),
+            max: self.max.min(max),
+        };
+        if narrowed.min <= narrowed.max {
+            Some(narrowed)
+        } else {
+            None
+        }
+    }
+
+    // Results that don't fit in an i64 stop the program, so clamping the
+    // bounds to i64 doesn't lose anything.
+    fn from_candidates<I: Iterator<Item = i64>>(candidates: I) -> Option<Interval> {
+        candidates.fold(None, |acc: Option<Interval>, n| match acc {
+            Some(i) => Some(i.join(&Interval::exactly(n))),
+            None => Some(Interval::exactly(n)),
+        })
+    }
+
+    fn corners<F>(&self, other: &Interval, f: F) -> Option<Interval>
+    where
+        F: Fn(i64, i64) -> i64,
+    {
+        let corners = [
+            (self.min, other.min),
+            (self.min, other.max),
+            (self.max, other.min),
+            (self.max, other.max),
+        ];
+        Interval::from_candidates(corners.iter().map(|&(a, b)| f(a, b)))
+    }
+
+    // The non-zero parts of the interval, negative then positive
+    fn non_zero_parts(&self) -> Vec<Interval> {
+        let mut parts = Vec::new();
+        if let Some(negative) = self.narrow(i64::min_value(), -1) {
+            parts.push(negative);
+        }
+        if let Some(positive) = self.narrow(1, i64::max_value()) {
+            parts.push(positive);
+        }
+        parts
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}, {}]", self.min, self.max)
+    }
+}
+
+impl Op {
+    /// Everything the register can end up holding; `None` if running the
+    /// instruction always fails.
+    fn apply_interval(&self, current: &Interval, amount: &Interval) -> Option<Interval> {
+        match self {
+            &Op::Inc => current.corners(amount, |a, b| a.saturating_add(b)),
+            &Op::Dec => current.corners(amount, |a, b| a.saturating_sub(b)),
+            &Op::Set => Some(*amount),
+            &Op::Mul => current.corners(amount, |a, b| a.saturating_mul(b)),
+            // Truncating division is monotonic in both arguments as long as
+            // the divisor keeps its sign, so each part's corners bound it.
+            &Op::Div => amount
+                .non_zero_parts()
+                .iter()
+                .filter_map(|part| {
+                    current.corners(part, |a, b| a.checked_div(b).unwrap_or(i64::max_value()))
+                })
+                .fold(None, |acc: Option<Interval>, i| match acc {
+                    Some(acc) => Some(acc.join(&i)),
+                    None => Some(i),
+                }),
+            // The remainder is smaller than the divisor and no further from 0
+            // than the register, on the register's side of 0.
+            &Op::Mod => {
+                let largest_divisor = amount
+                    .non_zero_parts()
+                    .iter()
+                    .flat_map(|part| vec![part.min, part.max])
+                    .map(|b| b.checked_abs().unwrap_or(i64::max_value()))
+                    .max()?;
+                let bound = largest_divisor - 1;
+                Some(Interval {
+                    min: current.min.min(0).max(-bound),
+                    max: current.max.max(0).min(bound),
+                })
+            }
+        }
+    }
+}
+
+impl Cond {
+    /// Whether the comparison holds for every pair of values, or for none.
+    fn compare_intervals(&self, left: &Interval, right: &Interval) -> Option<bool> {
+        let (always, never) = match self {
+            &Cond::GT => (left.min > right.max, left.max <= right.min),
+            &Cond::LT => (left.max < right.min, left.min >= right.max),
+            &Cond::GTE => (left.min >= right.max, left.max < right.min),
+            &Cond::LTE => (left.max <= right.min, left.min > right.max),
+            &Cond::E => (
+                left.min == left.max && left == right,
+                left.max < right.min || left.min > right.max,
+            ),
+            &Cond::NE => (
+                left.max < right.min || left.min > right.max,
+                left.min == left.max && left == right,
+            ),
+        };
+        if always {
+            Some(true)
+        } else if never {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// `x cond y` is the same as `y flipped x`.
+    fn flipped(&self) -> Cond {
+        match self {
+            &Cond::GT => Cond::LT,
+            &Cond::LT => Cond::GT,
+            &Cond::GTE => Cond::LTE,
+            &Cond::LTE => Cond::GTE,
+            &Cond::E => Cond::E,
+            &Cond::NE => Cond::NE,
+        }
+    }
+
+    /// What's left of `left` once we know it compares this way to `right`.
+    fn narrow(&self, left: &Interval, right: &Interval) -> Option<Interval> {
+        let (min, max) = (i64::min_value(), i64::max_value());
+        match self {
+            &Cond::GT => left.narrow(right.min.saturating_add(1), max),
+            &Cond::LT => left.narrow(min, right.max.saturating_sub(1)),
+            &Cond::GTE => left.narrow(right.min, max),
+            &Cond::LTE => left.narrow(min, right.max),
+            &Cond::E => left.narrow(right.min, right.max),
+            &Cond::NE if right.min != right.max => Some(*left),
+            &Cond::NE if left.min == right.min => left.narrow(right.min.saturating_add(1), max),
+            &Cond::NE if left.max == right.min => left.narrow(min, right.min.saturating_sub(1)),
+            &Cond::NE => Some(*left),
+        }
+    }
+}
+
+/// A condition that holds every time its instruction runs, or never does.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ConstantCondition {
+    /// Index of the instruction in the program.
+    pub idx: usize,
+    pub condition: Condition,
+    pub always: bool,
+}
+
+/// What we can tell about a program without running it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Lint {
+    /// Registers that are compared or used as amounts but never written to, so
+    /// keep whatever they started with.
+    pub only_read: Vec<String>,
+    /// Registers that are written to but never compared or used as amounts.
+    pub only_written: Vec<String>,
+    /// Only the outermost part of each condition that is constant.
+    pub constant_conditions: Vec<ConstantCondition>,
+    /// Every value each register can hold while the program runs.
+    pub ranges: BTreeMap<String, Interval>,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.only_read.is_empty() {
+            writeln!(f, "Read but never written: {}", self.only_read.join(", "))?;
+        }
+        if !self.only_written.is_empty() {
+            writeln!(f, "Written but never read: {}", self.only_written.join(", "))?;
+        }
+        for c in self.constant_conditions.iter() {
+            let always = if c.always { "true" } else { "false" };
+            writeln!(f, "Instruction {}: `{}` is always {}", c.idx + 1, c.condition, always)?;
+        }
+        writeln!(f, "Ranges:")?;
+        for (name, range) in self.ranges.iter() {
+            writeln!(f, "  {}: {}", name, range)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks a program for registers and conditions that probably aren't doing
+/// what was meant, and works out the range of each register, given the range
+/// every register might start in.
+///
+/// Programs only ever run straight through, so this follows the range of
+/// every register from one instruction to the next, narrowing them by each
+/// condition for when it passes. Starting every register at 0, like the
+/// puzzle does, pins down every value exactly and so makes every condition
+/// constant.
+pub fn lint(instructions: &[Instruction], start: Interval) -> Lint {
+    let mut read = BTreeSet::new();
+    let mut written = BTreeSet::new();
+    for i in instructions.iter() {
+        let mut operands = vec![&i.amount];
+        if let Some(ref condition) = i.condition {
+            push_operands(condition, &mut operands);
+        }
+        for operand in operands {
+            if let &Operand::Register(ref name) = operand {
+                read.insert(name.as_str());
+            }
+        }
+        written.insert(i.register.as_str());
+    }
+    let mut current: HashMap<&str, Interval> =
+        read.union(&written).map(|name| (*name, start)).collect();
+    let mut ranges: BTreeMap<String, Interval> = current
+        .iter()
+        .map(|(name, range)| (name.to_string(), *range))
+        .collect();
+    let mut constant_conditions = Vec::new();
+    for (idx, i) in instructions.iter().enumerate() {
+        let before = interval_of(&Operand::Register(i.register.clone()), &current);
+        let outcome = match i.condition {
+            Some(ref condition) => {
+                push_constant_conditions(idx, condition, &current, &mut constant_conditions);
+                condition.holds_for_intervals(&current)
+            }
+            None => Some(true),
+        };
+        let ran = if outcome == Some(false) {
+            None
+        } else {
+            let narrow = |operand: &Operand| match i.condition {
+                Some(ref condition) => condition.narrow(operand, &current),
+                None => Some(interval_of(operand, &current)),
+            };
+            let register = Operand::Register(i.register.clone());
+            match (narrow(&register), narrow(&i.amount)) {
+                (Some(register), Some(amount)) => i.op.apply_interval(&register, &amount),
+                _ => None,
+            }
+        };
+        let after = match (ran, outcome) {
+            (Some(ran), Some(true)) => ran,
+            (Some(ran), _) => ran.join(&before),
+            (None, _) => before,
+        };
+        current.insert(i.register.as_str(), after);
+        if let Some(range) = ranges.get_mut(&i.register) {
+            *range = range.join(&after);
+        }
+    }
+    Lint {
+        only_read: read.difference(&written).map(|s| s.to_string()).collect(),
+        only_written: written.difference(&read).map(|s| s.to_string()).collect(),
+        constant_conditions,
+        ranges,
+    }
+}
+
+/// Reads a program from a file and lints it.
+pub fn lint_file<P>(path: P, start: Interval) -> Result<Lint, String>
 where
-    K: Eq + Hash,
-    V: Copy + Ord,
+    P: AsRef<Path>,
 {
-    hash.iter().fold(None, |acc, (_, v)| match acc {
-        None => Some(*v),
-        Some(old_v) => if *v > old_v {
-            Some(*v)
-        } else {
-            acc
+    let mut program = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut program))
+        .map_err(|e| format!("Could not read program: {}", e))?;
+    parse_instructions(program.trim_end()).map(|instructions| lint(&instructions, start))
+}
+
+// Every register the program mentions starts off in `registers`
+fn interval_of(operand: &Operand, registers: &HashMap<&str, Interval>) -> Interval {
+    match operand {
+        &Operand::Register(ref name) => registers[name.as_str()],
+        &Operand::Number(n) => Interval::exactly(n),
+    }
+}
+
+fn push_constant_conditions(
+    idx: usize,
+    condition: &Condition,
+    registers: &HashMap<&str, Interval>,
+    constant_conditions: &mut Vec<ConstantCondition>,
+) -> () {
+    match condition.holds_for_intervals(registers) {
+        Some(always) => constant_conditions.push(ConstantCondition {
+            idx,
+            condition: condition.clone(),
+            always,
+        }),
+        None => match condition {
+            &Condition::And(ref a, ref b) | &Condition::Or(ref a, ref b) => {
+                push_constant_conditions(idx, a, registers, constant_conditions);
+                push_constant_conditions(idx, b, registers, constant_conditions);
+            }
+            &Condition::Compare { .. } => (),
         },
-    })
+    }
 }
 
-macro_rules! instruction_parser {
+impl Condition {
+    /// Whether the condition holds for every value the registers might have,
+    /// or for none.
+    fn holds_for_intervals(&self, registers: &HashMap<&str, Interval>) -> Option<bool> {
+        match self {
+            // Always the same value on both sides, whatever it is
+            &Condition::Compare {
+                ref left,
+                cond,
+                ref right,
+            } if left == right => Some(cond.compare(0, 0)),
+            &Condition::Compare {
+                ref left,
+                cond,
+                ref right,
+            } => cond.compare_intervals(
+                &interval_of(left, registers),
+                &interval_of(right, registers),
+            ),
+            &Condition::And(ref a, ref b) => {
+                match (a.holds_for_intervals(registers), b.holds_for_intervals(registers)) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            &Condition::Or(ref a, ref b) => {
+                match (a.holds_for_intervals(registers), b.holds_for_intervals(registers)) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// What's left of `operand`'s values when the condition holds; `None` if
+    /// it can't.
+    fn narrow(&self, operand: &Operand, registers: &HashMap<&str, Interval>) -> Option<Interval> {
+        match self {
+            &Condition::Compare {
+                ref left,
+                cond,
+                ref right,
+            } => {
+                let interval = interval_of(operand, registers);
+                if left == right {
+                    return if cond.compare(0, 0) { Some(interval) } else { None };
+                }
+                let (left_interval, right_interval) =
+                    (interval_of(left, registers), interval_of(right, registers));
+                // Narrow whichever side isn't the operand too, in case that
+                // shows the comparison can't hold
+                let narrowed_left = cond.narrow(&left_interval, &right_interval)?;
+                let narrowed_right = cond.flipped().narrow(&right_interval, &left_interval)?;
+                if left == operand {
+                    Some(narrowed_left)
+                } else if right == operand {
+                    Some(narrowed_right)
+                } else {
+                    Some(interval)
+                }
+            }
+            &Condition::And(ref a, ref b) => {
+                let narrowed_a = a.narrow(operand, registers)?;
+                let narrowed_b = b.narrow(operand, registers)?;
+                narrowed_a.narrow(narrowed_b.min, narrowed_b.max)
+            }
+            &Condition::Or(ref a, ref b) => {
+                match (a.narrow(operand, registers), b.narrow(operand, registers)) {
+                    (Some(a), Some(b)) => Some(a.join(&b)),
+                    (a, b) => a.or(b),
+                }
+            }
+        }
+    }
+}
+
+macro_rules! operand_parser {
+    () => {
+        {
+        let number_parser = number_parser!(i64);
+        number_parser
+            .map(Operand::Number)
+            .or(many1::<String, _>(letter()).map(Operand::Register))
+        }
+    }
+}
+
+macro_rules! comparison_parser {
     () => {
         {
-        let identifier_parser = many1::<String, _>(letter());
-        let op_parser = try(string("inc"))
-            .map(|_| Op::Inc)
-            .or(string("dec").map(|_| Op::Dec));
-        let amount_parser = number_parser!(i64);
-        let cond_statement_parser = string("if");
-        let cond_target_parser = many1::<String, _>(letter());
         let cond_parser = (try(string(">=")).map(|_| Cond::GTE))
             .or(try(string("<=")).map(|_| Cond::LTE))
             .or(try(string("==")).map(|_| Cond::E))
             .or(try(string("!=")).map(|_| Cond::NE))
             .or(try(string(">")).map(|_| Cond::GT))
             .or(string("<").map(|_| Cond::LT));
-        let cond_amount_parser = number_parser!(i64);
+        operand_parser!()
+            .skip(tabs_or_spaces!())
+            .and(cond_parser.skip(tabs_or_spaces!()))
+            .and(operand_parser!())
+            .map(|((left, cond), right)| Condition::Compare { left, cond, right })
+        }
+    }
+}
+
+// Joins conditions separated by `keyword`, grouping them to the left
+macro_rules! joined_conditions_parser {
+    ($conditions: expr, $keyword: expr, $join: path) => {
+        sep_by1::<Vec<Condition>, _, _>(
+            $conditions,
+            try(tabs_or_spaces!().with(string($keyword)).skip(tabs_or_spaces!())),
+        ).map(|conditions| {
+            let mut conditions = conditions.into_iter();
+            let first = conditions.next().expect("sep_by1 gives at least one");
+            conditions.fold(first, |acc, next| $join(Box::new(acc), Box::new(next)))
+        })
+    }
+}
+
+macro_rules! condition_parser {
+    () => {
+        joined_conditions_parser!(
+            joined_conditions_parser!(comparison_parser!(), "and", Condition::And),
+            "or",
+            Condition::Or
+        )
+    }
+}
+
+macro_rules! instruction_parser {
+    () => {
+        {
+        let identifier_parser = many1::<String, _>(letter());
+        let op_parser = try(string("inc")).map(|_| Op::Inc)
+            .or(try(string("dec")).map(|_| Op::Dec))
+            .or(try(string("set")).map(|_| Op::Set))
+            .or(try(string("mul")).map(|_| Op::Mul))
+            .or(try(string("div")).map(|_| Op::Div))
+            .or(string("mod").map(|_| Op::Mod));
+        let cond_statement_parser = try(tabs_or_spaces!().with(string("if")));
         identifier_parser
             .skip(tabs_or_spaces!())
             .and(op_parser.skip(tabs_or_spaces!()))
-            .and(amount_parser.skip(tabs_or_spaces!()))
-            .skip(cond_statement_parser.skip(tabs_or_spaces!()))
-            .and(cond_target_parser.skip(tabs_or_spaces!()))
-            .and(cond_parser.skip(tabs_or_spaces!()))
-            .and(cond_amount_parser)
+            .and(operand_parser!())
+            .and(optional(
+                cond_statement_parser
+                    .skip(tabs_or_spaces!())
+                    .with(condition_parser!()),
+            ))
             .map(
-                |(
-                    (
-                        (((parsed_identifier, parsed_op), parsed_amount), parsed_cond_target),
-                        parsed_cond,
-                    ),
-                    parsed_cond_amount,
-                )| {
+                |(((parsed_identifier, parsed_op), parsed_amount), parsed_condition)| {
                     Instruction {
                         register: parsed_identifier,
                         op: parsed_op,
                         amount: parsed_amount,
-                        check_register: parsed_cond_target,
-                        cond: parsed_cond,
-                        cond_amount: parsed_cond_amount,
+                        condition: parsed_condition,
                     }
                 },
             )
@@ -184,6 +1030,15 @@ macro_rules! instruction_parser {
     }
 }
 
+/// Parses a single condition, as written after `if`, e.g. for `Steps::watch`.
+pub fn parse_condition(s: &str) -> Result<Condition, String> {
+    let mut parser = condition_parser!().skip(eof());
+    parser
+        .easy_parse(s.trim())
+        .map(|(condition, _)| condition)
+        .map_err(|e| e.to_string())
+}
+
 impl Instruction {
     // Wish I could separate this out into functions, but the whole impl trait story in Rust
     // makes that __reaally__ difficult
@@ -197,6 +1052,8 @@ impl Instruction {
 #[cfg(test)]
 mod tests {
     use day_8::*;
+    use common::SplitMix64;
+    use std::time::Instant;
 
     const TEST_INPUT: &str = r#"
 b inc 5 if a > 1
@@ -240,4 +1097,402 @@ c inc -20 if c == 10"#;
         )
     }
 
+    #[test]
+    fn parse_extended_test() {
+        let parsed =
+            parse_instructions("a set b if c >= d and e < 1 or f != g\nb mod -3\nc div a").unwrap();
+        let reg = |s: &str| Operand::Register(s.to_string());
+        let compare = |left, cond, right| Condition::Compare { left, cond, right };
+        assert_eq!(
+            parsed,
+            vec![
+                Instruction {
+                    register: "a".to_string(),
+                    op: Op::Set,
+                    amount: reg("b"),
+                    condition: Some(Condition::Or(
+                        Box::new(Condition::And(
+                            Box::new(compare(reg("c"), Cond::GTE, reg("d"))),
+                            Box::new(compare(reg("e"), Cond::LT, Operand::Number(1))),
+                        )),
+                        Box::new(compare(reg("f"), Cond::NE, reg("g"))),
+                    )),
+                },
+                Instruction {
+                    register: "b".to_string(),
+                    op: Op::Mod,
+                    amount: Operand::Number(-3),
+                    condition: None,
+                },
+                Instruction {
+                    register: "c".to_string(),
+                    op: Op::Div,
+                    amount: reg("a"),
+                    condition: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn display_round_trip_test() {
+        let program = "a set b if c >= d and e < 1 or f != g\nb mod -3\nc inc 5 if 1 == 1";
+        let parsed = parse_instructions(program).unwrap();
+        let displayed: Vec<_> = parsed.iter().map(|i| i.to_string()).collect();
+        assert_eq!(displayed.join("\n"), program);
+        let (original, _) = Instruction::parse(DAY_8_INPUT).unwrap();
+        let displayed: Vec<_> = original.iter().map(|i| i.to_string()).collect();
+        assert_eq!(parse_instructions(&displayed.join("\n")).unwrap(), original);
+    }
+
+    #[test]
+    fn simualate_extended_instructions_test() {
+        let program = r#"
+a set 7
+b set a if a > 0 and a < 10
+b mul b
+c set b
+c mod 10 if c > 100 or a == 0
+a div 2
+d dec a if 0 < a"#;
+        // a = 3, b = 49, c = 49, d = -3
+        assert_eq!(
+            simualate_instructions(program),
+            Ok(SimulationResult {
+                historical_highest_reg_value: Some(49),
+                current_highest_reg_value: Some(49),
+            })
+        );
+    }
+
+    #[test]
+    fn simualate_errors_test() {
+        assert_eq!(
+            simualate_instructions("a set 1\nb div 0 if a > 0"),
+            Err("Instruction 2 (b div 0 if a > 0): division by zero".to_string())
+        );
+        assert_eq!(
+            simualate_instructions("a set 9223372036854775807\na inc 1"),
+            Err("Instruction 2 (a inc 1): overflow".to_string())
+        );
+        assert!(simualate_instructions("a frobnicate 1").is_err());
+    }
+
+    #[test]
+    fn steps_test() {
+        let program = Program::parse(TEST_INPUT).unwrap();
+        let steps: Vec<_> = Steps::new(&program).map(|s| s.unwrap()).collect();
+        assert_eq!(steps.len(), 4);
+        let passed: Vec<_> = steps.iter().map(|s| s.condition_passed).collect();
+        assert_eq!(passed, vec![false, true, true, true]);
+        let deltas: Vec<_> = steps.iter().map(|s| s.delta()).collect();
+        assert_eq!(deltas, vec![Some(0), Some(1), Some(10), Some(-20)]);
+        assert_eq!(
+            steps[2],
+            Step {
+                idx: 2,
+                instruction: &program.instructions()[2],
+                condition_passed: true,
+                before: 0,
+                after: 10,
+                current_highest_reg_value: Some(10),
+                historical_highest_reg_value: Some(10),
+                triggered_watch: None,
+            }
+        );
+        assert_eq!(steps[3].current_highest_reg_value, Some(1));
+        assert_eq!(steps[3].historical_highest_reg_value, Some(10));
+    }
+
+    #[test]
+    fn steps_watch_test() {
+        let program = Program::parse(TEST_INPUT).unwrap();
+        let mut steps = Steps::new(&program)
+            .watch(parse_condition("b > 0").unwrap())
+            .watch(parse_condition("c >= 10").unwrap());
+        let ran: Vec<_> = steps.by_ref().map(|s| s.unwrap()).collect();
+        assert_eq!(ran.len(), 3);
+        assert_eq!(ran[2].triggered_watch, Some(1));
+        assert_eq!(steps.next(), None);
+        assert_eq!(
+            steps.snapshot(),
+            Snapshot {
+                executed: 3,
+                registers: btreemap!{"a".to_string() => 1, "c".to_string() => 10},
+                current_highest_reg_value: Some(10),
+                historical_highest_reg_value: Some(10),
+            }
+        );
+    }
+
+    #[test]
+    fn steps_peak_real_test() {
+        // Find the instruction that first takes a register to the overall peak
+        let program = Program::parse(DAY_8_INPUT).unwrap();
+        let peak = Steps::new(&program)
+            .map(|s| s.unwrap())
+            .find(|s| s.historical_highest_reg_value == Some(7037))
+            .unwrap();
+        assert!(peak.condition_passed);
+        assert_eq!(peak.after, 7037);
+    }
+
+    #[test]
+    fn steps_error_test() {
+        let program = Program::parse("a inc 1\nb div 0\nc inc 1").unwrap();
+        let steps: Vec<_> = Steps::new(&program).collect();
+        assert_eq!(steps.len(), 2);
+        assert!(steps[0].is_ok());
+        assert!(steps[1].is_err());
+    }
+
+    #[test]
+    fn parse_condition_test() {
+        assert_eq!(
+            parse_condition(" x > 100 "),
+            Ok(Condition::Compare {
+                left: Operand::Register("x".to_string()),
+                cond: Cond::GT,
+                right: Operand::Number(100),
+            })
+        );
+        assert!(parse_condition("x > 100 junk").is_err());
+    }
+
+    #[test]
+    fn program_test() {
+        let program = Program::parse(TEST_INPUT).unwrap();
+        assert_eq!(program.register_names(), &["b", "a", "c"]);
+        assert_eq!(program.instructions().len(), 4);
+    }
+
+    #[test]
+    fn highest_goes_down_test() {
+        let program = "a set 5\nb set 5\na set 1\nb set 3\nb dec 10\nc set -20\nb set 1";
+        let highest: Vec<_> = Steps::new(&Program::parse(program).unwrap())
+            .map(|s| s.unwrap().current_highest_reg_value)
+            .collect();
+        let expected = vec![5, 5, 5, 3, 1, 1, 1];
+        assert_eq!(highest, expected.into_iter().map(Some).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn watch_unknown_register_test() {
+        let program = Program::parse(TEST_INPUT).unwrap();
+        let ran = Steps::new(&program)
+            .watch(parse_condition("z != 0 or a > 0").unwrap())
+            .count();
+        assert_eq!(ran, 2);
+    }
+
+    #[test]
+    fn matches_hash_map_simulation_test() {
+        let mut rng = SplitMix64(8);
+        for _ in 0..50 {
+            let program = generate_program(&mut rng, 200, 8);
+            let instructions = parse_instructions(&program).unwrap();
+            assert_eq!(simualate_instructions(&program), Ok(hash_map_simulation(&instructions)));
+        }
+    }
+
+    // cargo test --release -- --ignored --nocapture simulation_benchmark
+    #[test]
+    #[ignore]
+    fn simulation_benchmark() {
+        let text = generate_program(&mut SplitMix64(2017), 1000000, 1000);
+        let program = Program::parse(&text).unwrap();
+        let start = Instant::now();
+        let mut simulation = Simulation::new(&program);
+        run_simulation(&mut simulation).unwrap();
+        let interned_time = start.elapsed();
+        let start = Instant::now();
+        let hashed = hash_map_simulation(program.instructions());
+        let hashed_time = start.elapsed();
+        assert_eq!(simulation.current_highest_reg_value, hashed.current_highest_reg_value);
+        println!("Interned registers: {:?}", interned_time);
+        println!("HashMap registers:  {:?}", hashed_time);
+    }
+
+    fn generate_program(rng: &mut SplitMix64, len: usize, register_count: usize) -> String {
+        let ops = ["inc", "dec", "set"];
+        let conds = [">", "<", ">=", "<=", "==", "!="];
+        let mut lines = Vec::with_capacity(len);
+        for _ in 0..len {
+            let register = register_name(rng.below(register_count));
+            let op = ops[rng.below(ops.len())];
+            let amount = rng.below(2001) as i64 - 1000;
+            let compared = register_name(rng.below(register_count));
+            let cond = conds[rng.below(conds.len())];
+            let against = rng.below(201) as i64 - 100;
+            lines.push(format!(
+                "{} {} {} if {} {} {}",
+                register, op, amount, compared, cond, against
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn register_name(mut idx: usize) -> String {
+        let mut name = String::new();
+        loop {
+            name.push((b'a' + (idx % 26) as u8) as char);
+            idx /= 26;
+            if idx == 0 {
+                return name;
+            }
+        }
+    }
+
+    // How simulations used to run: registers in a HashMap, looking at all of
+    // them after every instruction for the highest.
+    fn hash_map_simulation(instructions: &[Instruction]) -> SimulationResult {
+        let mut registers: HashMap<&str, i64> = HashMap::new();
+        let mut historical_highest_reg_value = None;
+        let mut current_highest_reg_value = None;
+        for i in instructions.iter() {
+            let passed = match i.condition {
+                Some(ref condition) => holds(condition, &registers),
+                None => true,
+            };
+            if passed {
+                let amount = value(&i.amount, &registers);
+                let current = *registers.get(i.register.as_str()).unwrap_or(&0);
+                registers.insert(i.register.as_str(), i.op.apply(current, amount).unwrap());
+            }
+            current_highest_reg_value = registers.values().max().cloned();
+            if current_highest_reg_value > historical_highest_reg_value {
+                historical_highest_reg_value = current_highest_reg_value;
+            }
+        }
+        SimulationResult {
+            historical_highest_reg_value,
+            current_highest_reg_value,
+        }
+    }
+
+    fn value(operand: &Operand, registers: &HashMap<&str, i64>) -> i64 {
+        match operand {
+            &Operand::Register(ref name) => *registers.get(name.as_str()).unwrap_or(&0),
+            &Operand::Number(n) => n,
+        }
+    }
+
+    fn holds(condition: &Condition, registers: &HashMap<&str, i64>) -> bool {
+        match condition {
+            &Condition::Compare {
+                ref left,
+                cond,
+                ref right,
+            } => cond.compare(value(left, registers), value(right, registers)),
+            &Condition::And(ref a, ref b) => holds(a, registers) && holds(b, registers),
+            &Condition::Or(ref a, ref b) => holds(a, registers) || holds(b, registers),
+        }
+    }
+
+    const LINT_INPUT: &str = r#"
+a inc 1
+b set a if a > 0
+c inc 5 if d > 0
+b dec 1 if b > 10
+e set 2 if a == a
+f mul 3 if a < 5 and x == 1
+g inc 1 if 1 == 1 or a > 1"#;
+
+    #[test]
+    fn lint_from_zero_test() {
+        let instructions = parse_instructions(LINT_INPUT).unwrap();
+        let lint = lint(&instructions, Interval::exactly(0));
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(lint.only_read, strings(&["d", "x"]));
+        assert_eq!(lint.only_written, strings(&["c", "e", "f", "g"]));
+        let constant: Vec<_> = lint.constant_conditions
+            .iter()
+            .map(|c| (c.idx, c.condition.to_string(), c.always))
+            .collect();
+        assert_eq!(
+            constant,
+            vec![
+                (1, "a > 0".to_string(), true),
+                (2, "d > 0".to_string(), false),
+                (3, "b > 10".to_string(), false),
+                (4, "a == a".to_string(), true),
+                (5, "a < 5 and x == 1".to_string(), false),
+                (6, "1 == 1 or a > 1".to_string(), true),
+            ]
+        );
+        let range = |min, max| Interval { min, max };
+        assert_eq!(
+            lint.ranges,
+            btreemap!{
+                "a".to_string() => range(0, 1),
+                "b".to_string() => range(0, 1),
+                "c".to_string() => range(0, 0),
+                "d".to_string() => range(0, 0),
+                "e".to_string() => range(0, 2),
+                "f".to_string() => range(0, 0),
+                "g".to_string() => range(0, 1),
+                "x".to_string() => range(0, 0),
+            }
+        );
+    }
+
+    #[test]
+    fn lint_unknown_start_test() {
+        let program = r#"
+a set 3 if b > 0
+c set a if a > 5
+c mod 4 if c != 0
+d inc 1 if b > 5 and b <= 20
+d div 0 if d > 3
+e inc 1 if e >= 3 or e < -3
+f dec 1 if b > 1 or 1 == 2"#;
+        let instructions = parse_instructions(program).unwrap();
+        let lint = lint(&instructions, Interval { min: -10, max: 10 });
+        let constant: Vec<_> = lint.constant_conditions
+            .iter()
+            .map(|c| (c.idx, c.condition.to_string(), c.always))
+            .collect();
+        assert_eq!(
+            constant,
+            vec![(3, "b <= 20".to_string(), true), (6, "1 == 2".to_string(), false)]
+        );
+        let range = |min, max| Interval { min, max };
+        // a is 3 or whatever it started at
+        assert_eq!(lint.ranges["a"], range(-10, 10));
+        assert_eq!(lint.ranges["c"], range(-10, 10));
+        assert_eq!(lint.ranges["d"], range(-10, 11));
+        assert_eq!(lint.ranges["e"], range(-10, 11));
+        assert_eq!(lint.ranges["f"], range(-11, 10));
+    }
+
+    #[test]
+    fn interval_ops_test() {
+        let range = |min, max| Interval { min, max };
+        assert_eq!(Op::Mul.apply_interval(&range(-2, 3), &range(-5, 4)), Some(range(-15, 12)));
+        assert_eq!(Op::Div.apply_interval(&range(-7, 9), &range(-2, 3)), Some(range(-9, 9)));
+        assert_eq!(Op::Div.apply_interval(&range(1, 9), &range(0, 0)), None);
+        assert_eq!(Op::Mod.apply_interval(&range(-7, 9), &range(0, 3)), Some(range(-2, 2)));
+        assert_eq!(Op::Mod.apply_interval(&range(1, 9), &range(-3, 0)), Some(range(0, 2)));
+        let max = i64::max_value();
+        assert_eq!(Op::Inc.apply_interval(&range(0, max), &range(1, 2)), Some(range(1, max)));
+    }
+
+    #[test]
+    fn lint_matches_simulation_test() {
+        // Starting from 0 every register can only have one value at a time
+        let mut rng = SplitMix64(48);
+        for _ in 0..20 {
+            let program = generate_program(&mut rng, 100, 6);
+            let instructions = parse_instructions(&program).unwrap();
+            let lint = lint(&instructions, Interval::exactly(0));
+            let program = Program::new(instructions);
+            let mut steps = Steps::new(&program);
+            while let Some(step) = steps.next() {
+                let step = step.unwrap();
+                let range = lint.ranges[&step.instruction.register];
+                assert!(range.min <= step.after && step.after <= range.max);
+            }
+            assert_eq!(lint.constant_conditions.len(), program.instructions().len());
+        }
+    }
 }
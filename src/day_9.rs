@@ -5,21 +5,22 @@ const CLOSE_GROUP: char = '}';
 const OPEN_GARBAGE: char = '<';
 const CLOSE_GARBAGE: char = '>';
 const SKIP_NEXT_GARBAGE: char = '!';
+const SEPARATOR: char = ',';
 
 const DAY_9_INPUT: &'static str = include_str!("../data/day_9_input");
 
-pub fn run() -> Result<(), &'static str> {
+pub fn run() -> Result<(), String> {
     println!("*** Day 9: Stream Processing ***");
     println!("Input: {}", DAY_9_INPUT);
-    println!("Solution: {}\n", count_groups(DAY_9_INPUT));
+    let stats = count_groups(DAY_9_INPUT, Mode::Checked).map_err(|e| e.to_string())?;
+    println!("Solution: {}\n", stats);
     Ok(())
 }
 
 #[derive(Debug, PartialEq, Eq)]
-struct StreamStats {
-    sums: Vec<usize>, // for debugging
-    total_groups: usize,
-    total_garbage: usize,
+pub struct StreamStats {
+    pub total_groups: usize,
+    pub total_garbage: usize,
 }
 
 impl fmt::Display for StreamStats {
@@ -32,47 +33,368 @@ impl fmt::Display for StreamStats {
     }
 }
 
-fn count_groups(s: &str) -> StreamStats {
-    let mut current_level = 0;
-    let mut ignore_next = false;
-    let mut in_garbage = false;
-
-    s.chars().fold(
-        StreamStats {
-            sums: Vec::with_capacity(s.len() / 2),
-            total_groups: 0,
-            total_garbage: 0,
-        },
-        |mut acc, next| {
-            if in_garbage {
-                if ignore_next {
-                    // cancel
-                    ignore_next = false;
-                } else if next == CLOSE_GARBAGE {
-                    in_garbage = false;
-                    ignore_next = false; // just in case
-                } else if next == SKIP_NEXT_GARBAGE {
-                    ignore_next = true;
-                } else {
-                    acc.total_garbage += 1;
+/// The total score of the groups in the stream and how much garbage it holds.
+///
+/// In `Mode::Lenient`, groups still open at the end of the input count as
+/// closed there, so `{{}` scores 3 rather than just 2 for the inner group.
+pub fn count_groups(s: &str, mode: Mode) -> Result<StreamStats, InvalidStream> {
+    let stream = Stream::parse(s, mode)?;
+    Ok(StreamStats {
+        total_groups: stream.score(),
+        total_garbage: stream.garbage_len(),
+    })
+}
+
+/// Whether to insist on a well-formed stream, or make what we can of it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Mode {
+    Checked,
+    Lenient,
+}
+
+/// What came last in the group being parsed, or at the top level.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Previous {
+    Start,
+    Node,
+    Comma(usize),
+}
+
+/// Something wrong with a stream's structure.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StreamProblem {
+    UnmatchedClose,
+    UnclosedGroup,
+    UnterminatedGarbage,
+    StrayCharacter { character: char },
+    MissingComma,
+    UnexpectedComma,
+}
+
+impl fmt::Display for StreamProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &StreamProblem::UnmatchedClose => write!(f, "`}}` with no group to close"),
+            &StreamProblem::UnclosedGroup => write!(f, "group is never closed"),
+            &StreamProblem::UnterminatedGarbage => write!(f, "garbage is never closed"),
+            &StreamProblem::StrayCharacter { character } => {
+                write!(f, "stray {:?} outside garbage", character)
+            }
+            &StreamProblem::MissingComma => write!(f, "missing `,` before this"),
+            &StreamProblem::UnexpectedComma => write!(f, "`,` with nothing on one side"),
+        }
+    }
+}
+
+/// A problem, where it is in the stream in bytes, and the stream around it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StreamError {
+    pub offset: usize,
+    pub problem: StreamProblem,
+    /// With control characters escaped, so it fits on one line.
+    pub context: String,
+    /// Where the problem starts in `context`, in characters.
+    pub context_column: usize,
+}
+
+const CONTEXT_CHARS: usize = 10;
+
+impl StreamError {
+    fn new(s: &str, offset: usize, problem: StreamProblem) -> StreamError {
+        let before: Vec<_> = s[..offset].char_indices().rev().take(CONTEXT_CHARS).collect();
+        let from = before.last().map(|&(idx, _)| idx).unwrap_or(offset);
+        let to = s[offset..]
+            .char_indices()
+            .nth(CONTEXT_CHARS + 1)
+            .map(|(idx, _)| offset + idx)
+            .unwrap_or(s.len());
+        let escaped_before = escape_context(&s[from..offset]);
+        StreamError {
+            offset,
+            problem,
+            context_column: escaped_before.chars().count(),
+            context: escaped_before + &escape_context(&s[offset..to]),
+        }
+    }
+}
+
+fn escape_context(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        if c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let caret: String = ::std::iter::repeat(' ').take(self.context_column).collect();
+        write!(
+            f,
+            "Byte {}: {}\n  {}\n  {}^",
+            self.offset, self.problem, self.context, caret
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InvalidStream(pub Vec<StreamError>);
+
+impl fmt::Display for InvalidStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problems: Vec<_> = self.0.iter().map(|p| p.to_string()).collect();
+        write!(f, "Invalid stream:\n{}", problems.join("\n"))
+    }
+}
+
+/// Checks that the stream is well-formed, reporting every problem in order.
+pub fn validate_stream(s: &str) -> Result<(), InvalidStream> {
+    Stream::parse(s, Mode::Checked).map(|_| ())
+}
+
+/// A range of bytes in a stream, from `start` up to but not including `end`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Node {
+    Group(Group),
+    Garbage(Garbage),
+}
+
+/// `{...}`; `depth` is 1 for an outermost group, which is also its score.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Group {
+    pub span: Span,
+    pub depth: usize,
+    pub children: Vec<Node>,
+}
+
+/// `<...>`; `depth` is how many groups it's in, and `contents` is what's left
+/// once `!` has cancelled whatever comes after it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Garbage {
+    pub span: Span,
+    pub depth: usize,
+    pub contents: String,
+}
+
+/// Everything at the top level of a stream.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Stream {
+    pub nodes: Vec<Node>,
+}
+
+const INDENT: &str = "  ";
+
+impl Stream {
+    /// In `Mode::Checked`, only well-formed streams are parsed, where each
+    /// group and the top level hold groups and garbage separated by commas.
+    /// Whitespace between them doesn't matter.
+    ///
+    /// In `Mode::Lenient`, anything outside garbage that isn't a group or
+    /// garbage is skipped, as are `}` with no group to close, and groups and
+    /// garbage still open at the end of the input end there.
+    pub fn parse(s: &str, mode: Mode) -> Result<Stream, InvalidStream> {
+        let (stream, problems) = Stream::parse_with_problems(s);
+        if mode == Mode::Lenient || problems.is_empty() {
+            Ok(stream)
+        } else {
+            Err(InvalidStream(problems))
+        }
+    }
+
+    fn parse_with_problems(s: &str) -> (Stream, Vec<StreamError>) {
+        let mut problems = Vec::new();
+        // Groups that haven't been closed yet, with what's been found in them
+        let mut open: Vec<(usize, Vec<Node>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut previous = Previous::Start;
+        let mut chars = s.char_indices();
+        while let Some((idx, c)) = chars.next() {
+            let node = match c {
+                OPEN_GROUP => {
+                    if previous == Previous::Node {
+                        problems.push((idx, StreamProblem::MissingComma));
+                    }
+                    open.push((idx, Vec::new()));
+                    previous = Previous::Start;
+                    continue;
                 }
-            } else {
-                if next == OPEN_GROUP {
-                    current_level += 1;
-                } else if next == CLOSE_GROUP {
-                    if current_level != 0 {
-                        acc.sums.push(current_level);
-                        acc.total_groups += current_level;
-                        current_level -= 1;
+                CLOSE_GROUP => match open.pop() {
+                    Some((start, children)) => {
+                        if let Previous::Comma(comma) = previous {
+                            problems.push((comma, StreamProblem::UnexpectedComma));
+                        }
+                        Node::Group(Group {
+                            span: Span {
+                                start,
+                                end: idx + 1,
+                            },
+                            depth: open.len() + 1,
+                            children,
+                        })
+                    }
+                    None => {
+                        problems.push((idx, StreamProblem::UnmatchedClose));
+                        continue;
                     }
-                } else if next == OPEN_GARBAGE {
-                    in_garbage = true;
-                    ignore_next = false; // just in case
+                },
+                OPEN_GARBAGE => {
+                    if previous == Previous::Node {
+                        problems.push((idx, StreamProblem::MissingComma));
+                    }
+                    let mut contents = String::new();
+                    let mut end = None;
+                    while let Some((garbage_idx, c)) = chars.next() {
+                        match c {
+                            SKIP_NEXT_GARBAGE => {
+                                chars.next();
+                            }
+                            CLOSE_GARBAGE => {
+                                end = Some(garbage_idx + 1);
+                                break;
+                            }
+                            c => contents.push(c),
+                        }
+                    }
+                    if end.is_none() {
+                        problems.push((idx, StreamProblem::UnterminatedGarbage));
+                    }
+                    Node::Garbage(Garbage {
+                        span: Span {
+                            start: idx,
+                            end: end.unwrap_or(s.len()),
+                        },
+                        depth: open.len(),
+                        contents,
+                    })
+                }
+                SEPARATOR => {
+                    if previous == Previous::Node {
+                        previous = Previous::Comma(idx);
+                    } else {
+                        problems.push((idx, StreamProblem::UnexpectedComma));
+                    }
+                    continue;
+                }
+                // Layout, like pretty_print's
+                c if c.is_whitespace() => continue,
+                c => {
+                    problems.push((idx, StreamProblem::StrayCharacter { character: c }));
+                    continue;
+                }
+            };
+            previous = Previous::Node;
+            match open.last_mut() {
+                Some(&mut (_, ref mut children)) => children.push(node),
+                None => nodes.push(node),
+            }
+        }
+        // Whatever's still open, the comma has nothing after it
+        if let Previous::Comma(comma) = previous {
+            problems.push((comma, StreamProblem::UnexpectedComma));
+        }
+        while let Some((start, children)) = open.pop() {
+            problems.push((start, StreamProblem::UnclosedGroup));
+            let group = Node::Group(Group {
+                span: Span {
+                    start,
+                    end: s.len(),
+                },
+                depth: open.len() + 1,
+                children,
+            });
+            match open.last_mut() {
+                Some(&mut (_, ref mut children)) => children.push(group),
+                None => nodes.push(group),
+            }
+        }
+        problems.sort_by_key(|&(offset, _)| offset);
+        let errors = problems
+            .into_iter()
+            .map(|(offset, problem)| StreamError::new(s, offset, problem))
+            .collect();
+        (Stream { nodes }, errors)
+    }
+
+    /// The sum of every group's depth.
+    pub fn score(&self) -> usize {
+        self.fold(0, |acc, node| match node {
+            &Node::Group(ref g) => acc + g.depth,
+            &Node::Garbage(_) => acc,
+        })
+    }
+
+    /// How many characters of garbage there are, not counting cancelled ones.
+    pub fn garbage_len(&self) -> usize {
+        self.fold(0, |acc, node| match node {
+            &Node::Group(_) => acc,
+            &Node::Garbage(ref g) => acc + g.contents.chars().count(),
+        })
+    }
+
+    fn fold<A, F>(&self, init: A, f: F) -> A
+    where
+        F: Fn(A, &Node) -> A,
+    {
+        let mut acc = init;
+        let mut to_visit: Vec<&Node> = self.nodes.iter().collect();
+        while let Some(node) = to_visit.pop() {
+            acc = f(acc, node);
+            if let &Node::Group(ref g) = node {
+                to_visit.extend(g.children.iter());
+            }
+        }
+        acc
+    }
+
+    /// The stream with one thing per line, indented by how deep it is.
+    /// Cancelled characters are dropped, which can't change what it parses to.
+    pub fn pretty_print(&self) -> String {
+        let mut out = String::new();
+        write_nodes(&self.nodes, 0, &mut out);
+        out
+    }
+}
+
+fn write_nodes(nodes: &[Node], indent: usize, out: &mut String) -> () {
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 {
+            out.push_str(",\n");
+        }
+        for _ in 0..indent {
+            out.push_str(INDENT);
+        }
+        match node {
+            &Node::Garbage(ref g) => {
+                out.push(OPEN_GARBAGE);
+                out.push_str(&g.contents);
+                out.push(CLOSE_GARBAGE);
+            }
+            &Node::Group(ref g) if g.children.is_empty() => {
+                out.push(OPEN_GROUP);
+                out.push(CLOSE_GROUP);
+            }
+            &Node::Group(ref g) => {
+                out.push(OPEN_GROUP);
+                out.push('\n');
+                write_nodes(&g.children, indent + 1, out);
+                out.push('\n');
+                for _ in 0..indent {
+                    out.push_str(INDENT);
                 }
+                out.push(CLOSE_GROUP);
             }
-            acc
-        },
-    )
+        }
+    }
 }
 
 #[cfg(test)]
@@ -81,31 +403,225 @@ mod tests {
 
     #[test]
     fn count_groups_test() {
-        assert_eq!(count_groups("{}").total_groups, 1);
-        assert_eq!(count_groups("{{}}").total_groups, 3);
-        assert_eq!(count_groups("{{{}}}").total_groups, 6);
-        assert_eq!(count_groups("{{},{}},").total_groups, 5);
-        assert_eq!(count_groups("{{{},{},{{}}}},").total_groups, 16);
-        assert_eq!(count_groups("{<a>,<a>,<a>,<a>},").total_groups, 1);
+        let score = |s| count_groups(s, Mode::Lenient).unwrap().total_groups;
+        assert_eq!(score("{}"), 1);
+        assert_eq!(score("{{}}"), 3);
+        assert_eq!(score("{{{}}}"), 6);
+        assert_eq!(score("{{},{}},"), 5);
+        assert_eq!(score("{{{},{},{{}}}},"), 16);
+        assert_eq!(score("{<a>,<a>,<a>,<a>},"), 1);
+        assert_eq!(score("{{<ab>},{<ab>},{<ab>},{<ab>}},"), 9);
+        assert_eq!(score("{{<!!>},{<!!>},{<!!>},{<!!>}},"), 9);
+        assert_eq!(score("{{<a!>},{<a!>},{<a!>},{<ab>}},"), 3);
+        // Unclosed groups get closed at the end
+        assert_eq!(score("{{}"), 3);
+    }
+
+    #[test]
+    fn count_groups_real_test() {
+        let r = count_groups(DAY_9_INPUT, Mode::Checked).unwrap();
+        assert_eq!(r.total_groups, 21037);
+        assert_eq!(r.total_garbage, 9495);
+    }
+
+    #[test]
+    fn parse_test() {
+        let stream = Stream::parse("{{<a!>b>},x{}}", Mode::Lenient).unwrap();
         assert_eq!(
-            count_groups("{{<ab>},{<ab>},{<ab>},{<ab>}},").total_groups,
-            9
+            stream.nodes,
+            vec![
+                Node::Group(Group {
+                    span: Span { start: 0, end: 14 },
+                    depth: 1,
+                    children: vec![
+                        Node::Group(Group {
+                            span: Span { start: 1, end: 9 },
+                            depth: 2,
+                            children: vec![
+                                Node::Garbage(Garbage {
+                                    span: Span { start: 2, end: 8 },
+                                    depth: 2,
+                                    contents: "ab".to_string(),
+                                }),
+                            ],
+                        }),
+                        Node::Group(Group {
+                            span: Span { start: 11, end: 13 },
+                            depth: 2,
+                            children: vec![],
+                        }),
+                    ],
+                }),
+            ]
         );
+    }
+
+    #[test]
+    fn parse_garbage_test() {
+        let contents = |s| match Stream::parse(s, Mode::Lenient).unwrap().nodes[0] {
+            Node::Garbage(ref g) => g.contents.clone(),
+            _ => panic!("not garbage"),
+        };
+        assert_eq!(contents("<>"), "");
+        assert_eq!(contents("<random characters>"), "random characters");
+        assert_eq!(contents("<<<<>"), "<<<");
+        assert_eq!(contents("<{!>}>"), "{}");
+        assert_eq!(contents("<!!>"), "");
+        assert_eq!(contents("<!!!>>"), "");
+        assert_eq!(contents("<{o\"i!a,<{i<a>"), "{o\"i,<{i<a");
+        // Spans count bytes, not characters
         assert_eq!(
-            count_groups("{{<!!>},{<!!>},{<!!>},{<!!>}},").total_groups,
-            9
+            Stream::parse("<é>{}", Mode::Lenient).unwrap().nodes[1],
+            Node::Group(Group {
+                span: Span { start: 4, end: 6 },
+                depth: 1,
+                children: vec![],
+            })
         );
+    }
+
+    #[test]
+    fn parse_unclosed_test() {
+        let stream = Stream::parse("}{{<ab", Mode::Lenient).unwrap();
         assert_eq!(
-            count_groups("{{<a!>},{<a!>},{<a!>},{<ab>}},").total_groups,
-            3
+            stream.nodes,
+            vec![
+                Node::Group(Group {
+                    span: Span { start: 1, end: 6 },
+                    depth: 1,
+                    children: vec![
+                        Node::Group(Group {
+                            span: Span { start: 2, end: 6 },
+                            depth: 2,
+                            children: vec![
+                                Node::Garbage(Garbage {
+                                    span: Span { start: 3, end: 6 },
+                                    depth: 2,
+                                    contents: "ab".to_string(),
+                                }),
+                            ],
+                        }),
+                    ],
+                }),
+            ]
         );
     }
 
     #[test]
-    fn count_groups_real_test() {
-        let r = count_groups(DAY_9_INPUT);
-        assert_eq!(r.total_groups, 21037);
-        assert_eq!(r.total_garbage, 9495);
+    fn count_garbage_test() {
+        let garbage = |s| count_groups(s, Mode::Checked).unwrap().total_garbage;
+        assert_eq!(garbage("<>"), 0);
+        assert_eq!(garbage("<random characters>"), 17);
+        assert_eq!(garbage("<<<<>"), 3);
+        assert_eq!(garbage("<{!>}>"), 2);
+        assert_eq!(garbage("<!!>"), 0);
+        assert_eq!(garbage("<!!!>>"), 0);
+        assert_eq!(garbage("{<{o\"i!a,<{i<a>}"), 10);
     }
 
+    #[test]
+    fn pretty_print_test() {
+        let stream = Stream::parse("{{<!>a!!>},{},{{<>},<b>}},<c>", Mode::Checked).unwrap();
+        let expected = r#"{
+  {
+    <a>
+  },
+  {},
+  {
+    {
+      <>
+    },
+    <b>
+  }
+},
+<c>"#;
+        assert_eq!(stream.pretty_print(), expected);
+    }
+
+    #[test]
+    fn pretty_print_round_trip_test() {
+        let pretty = Stream::parse(DAY_9_INPUT, Mode::Lenient).unwrap().pretty_print();
+        let reparsed = Stream::parse(&pretty, Mode::Lenient).unwrap();
+        assert_eq!(reparsed.score(), 21037);
+        assert_eq!(reparsed.garbage_len(), 9495);
+        assert_eq!(reparsed.pretty_print(), pretty);
+    }
+
+    #[test]
+    fn pretty_print_checked_round_trip_test() {
+        for input in vec!["{{<a>},{}}", "{{<!>a!!>},{},{{<>},<b>}},<c>", DAY_9_INPUT] {
+            let stream = Stream::parse(input, Mode::Checked).unwrap();
+            let pretty = stream.pretty_print();
+            let reparsed = Stream::parse(&pretty, Mode::Checked).unwrap();
+            assert_eq!(reparsed.score(), stream.score());
+            assert_eq!(reparsed.garbage_len(), stream.garbage_len());
+            assert_eq!(reparsed.pretty_print(), pretty);
+        }
+    }
+
+    #[test]
+    fn validate_stream_test() {
+        for stream in vec!["", "{}", "{{},{}}", "{<a>,<a>},<>", "{{<!>},{}>}}\n", DAY_9_INPUT] {
+            assert_eq!(validate_stream(stream), Ok(()));
+        }
+        let problems = |s| match validate_stream(s) {
+            Ok(_) => vec![],
+            Err(InvalidStream(errors)) => errors
+                .into_iter()
+                .map(|e| (e.offset, e.problem))
+                .collect(),
+        };
+        assert_eq!(problems("}{}"), vec![(0, StreamProblem::UnmatchedClose)]);
+        assert_eq!(problems("{{}"), vec![(0, StreamProblem::UnclosedGroup)]);
+        assert_eq!(
+            problems("{<ab!>"),
+            vec![(0, StreamProblem::UnclosedGroup), (1, StreamProblem::UnterminatedGarbage)]
+        );
+        assert_eq!(
+            problems("{a} "),
+            vec![(1, StreamProblem::StrayCharacter { character: 'a' })]
+        );
+        assert_eq!(problems("{ }\n{}"), vec![(4, StreamProblem::MissingComma)]);
+        assert_eq!(problems("{{}<>}"), vec![(3, StreamProblem::MissingComma)]);
+        assert_eq!(problems("{,}"), vec![(1, StreamProblem::UnexpectedComma)]);
+        assert_eq!(problems("{{},}"), vec![(3, StreamProblem::UnexpectedComma)]);
+        assert_eq!(problems("{{},,{}}"), vec![(4, StreamProblem::UnexpectedComma)]);
+        assert_eq!(
+            problems("{,,}"),
+            vec![(1, StreamProblem::UnexpectedComma), (2, StreamProblem::UnexpectedComma)]
+        );
+        assert_eq!(problems("{},"), vec![(2, StreamProblem::UnexpectedComma)]);
+        assert_eq!(
+            problems("{{},"),
+            vec![(0, StreamProblem::UnclosedGroup), (3, StreamProblem::UnexpectedComma)]
+        );
+    }
+
+    #[test]
+    fn stream_error_display_test() {
+        let error = StreamError::new("{{<a>},{<b>}}}\n{}", 13, StreamProblem::UnmatchedClose);
+        assert_eq!(error.context, "a>},{<b>}}}\\n{}");
+        assert_eq!(
+            error.to_string(),
+            "Byte 13: `}` with no group to close\n  a>},{<b>}}}\\n{}\n            ^"
+        );
+        let error = StreamError::new("{é", 0, StreamProblem::UnclosedGroup);
+        assert_eq!(error.to_string(), "Byte 0: group is never closed\n  {é\n  ^");
+    }
+
+    #[test]
+    fn count_groups_modes_test() {
+        let stream = "{{},{}},";
+        assert_eq!(count_groups(stream, Mode::Lenient).unwrap().total_groups, 5);
+        assert_eq!(
+            count_groups(stream, Mode::Checked).map_err(|e| e.to_string()),
+            Err("Invalid stream:\nByte 7: `,` with nothing on one side\n  {{},{}},\n         ^"
+                .to_string())
+        );
+        assert!(Stream::parse("{{}", Mode::Checked).is_err());
+        assert_eq!(
+            Stream::parse("{{}}", Mode::Checked),
+            Stream::parse("{{}}", Mode::Lenient)
+        );
+    }
 }
@@ -37,3 +37,5 @@ pub mod day_21;
 pub mod day_22;
 pub mod day_23;
 pub mod day_24;
+
+pub use common::{find_cycle, Cycle};
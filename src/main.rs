@@ -4,7 +4,7 @@ extern crate clap;
 use std::str::FromStr;
 use std::fmt::Display;
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use std::error::Error;
 use std::process::exit;
 
@@ -46,6 +46,7 @@ fn main_result() -> Result<(), Box<Error>> {
     let matches = App::new("Advent of Code 2017")
         .version(version().as_str())
         .about("Solutions to AoC 2017 !")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("day")
                 .required(true)
@@ -53,7 +54,73 @@ fn main_result() -> Result<(), Box<Error>> {
                 .index(1)
                 .help("Which day's solution you want to run"),
         )
+        .subcommand(
+            SubCommand::with_name("passphrases")
+                .about("Generates random passphrases that are valid according to day 4")
+                .arg(
+                    Arg::with_name("wordlist")
+                        .required(true)
+                        .takes_value(true)
+                        .index(1)
+                        .help("File with the words to pick from, separated by whitespace"),
+                )
+                .arg(
+                    Arg::with_name("count")
+                        .long("count")
+                        .short("n")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("How many passphrases to generate"),
+                )
+                .arg(
+                    Arg::with_name("words")
+                        .long("words")
+                        .short("w")
+                        .takes_value(true)
+                        .default_value("5")
+                        .help("How many words to put in each passphrase"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .short("s")
+                        .takes_value(true)
+                        .default_value("2017")
+                        .help("Seed for the random number generator"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Checks a day 8 register program without running it")
+                .arg(
+                    Arg::with_name("program")
+                        .required(true)
+                        .takes_value(true)
+                        .index(1)
+                        .help("File with the program, one instruction per line"),
+                )
+                .arg(
+                    Arg::with_name("start-min")
+                        .long("start-min")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("Lowest value registers might start at (default: any)"),
+                )
+                .arg(
+                    Arg::with_name("start-max")
+                        .long("start-max")
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help("Highest value registers might start at (default: any)"),
+                ),
+        )
         .get_matches();
+    if let Some(sub_matches) = matches.subcommand_matches("passphrases") {
+        return generate_passphrases(sub_matches);
+    }
+    if let Some(sub_matches) = matches.subcommand_matches("lint") {
+        return lint_registers(sub_matches);
+    }
     match get_number("day", Some(0), &matches) {
         1 => day_1::run()?,
         2 => day_2::run()?,
@@ -83,6 +150,36 @@ fn main_result() -> Result<(), Box<Error>> {
     Ok(())
 }
 
+fn generate_passphrases<'a>(matches: &ArgMatches<'a>) -> Result<(), Box<Error>> {
+    let wordlist = matches.value_of("wordlist").ok_or("No wordlist given")?;
+    let count = get_number("count", None, matches);
+    let words = get_number("words", Some(0), matches);
+    let seed = get_number("seed", None, matches);
+    let generator = day_4::PassphraseGenerator::from_file(wordlist, words, seed)?;
+    for passphrase in generator.take(count) {
+        println!("{}", passphrase);
+    }
+    Ok(())
+}
+
+fn lint_registers<'a>(matches: &ArgMatches<'a>) -> Result<(), Box<Error>> {
+    let program = matches.value_of("program").ok_or("No program given")?;
+    let min = match matches.value_of("start-min") {
+        Some(s) => s.parse()?,
+        None => i64::min_value(),
+    };
+    let max = match matches.value_of("start-max") {
+        Some(s) => s.parse()?,
+        None => i64::max_value(),
+    };
+    if min > max {
+        Err("start-min should be at most start-max")?;
+    }
+    let lint = day_8::lint_file(program, day_8::Interval { min, max })?;
+    print!("{}", lint);
+    Ok(())
+}
+
 fn version() -> String {
     let (maj, min, pat) = (
         option_env!("CARGO_PKG_VERSION_MAJOR"),